@@ -0,0 +1,2 @@
+-- Executions interrupted by the runtime timeout
+ALTER TYPE execution_status ADD VALUE IF NOT EXISTS 'timeout';
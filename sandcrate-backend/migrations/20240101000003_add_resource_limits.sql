@@ -0,0 +1,6 @@
+-- Per-plugin resource limits (NULL falls back to the global defaults)
+ALTER TABLE plugins ADD COLUMN max_memory_bytes BIGINT;
+ALTER TABLE plugins ADD COLUMN max_table_elements INTEGER;
+
+-- Peak linear memory used by each execution
+ALTER TABLE plugin_executions ADD COLUMN peak_memory_bytes BIGINT;
@@ -0,0 +1,24 @@
+-- Queued plugin executions picked up by job workers
+CREATE TYPE job_status AS ENUM ('queued', 'running', 'completed', 'failed', 'cancelled');
+
+CREATE TABLE plugin_jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    plugin_id UUID NOT NULL REFERENCES plugins(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    parameters JSONB,
+    timeout_ms BIGINT,
+    status job_status NOT NULL DEFAULT 'queued',
+    execution_id UUID REFERENCES plugin_executions(id) ON DELETE SET NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMP WITH TIME ZONE,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_plugin_jobs_queued ON plugin_jobs(created_at) WHERE status = 'queued';
+CREATE INDEX idx_plugin_jobs_plugin_id ON plugin_jobs(plugin_id);
+
+CREATE TRIGGER update_plugin_jobs_updated_at BEFORE UPDATE ON plugin_jobs
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
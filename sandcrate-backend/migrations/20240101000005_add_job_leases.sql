@@ -0,0 +1,6 @@
+-- Track which worker holds a running job so jobs of crashed workers can be requeued
+ALTER TABLE plugin_jobs ADD COLUMN worker_id TEXT;
+ALTER TABLE plugin_jobs ADD COLUMN heartbeat_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE plugin_jobs ADD COLUMN lease_expires_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX idx_plugin_jobs_lease_expires_at ON plugin_jobs(lease_expires_at) WHERE status = 'running';
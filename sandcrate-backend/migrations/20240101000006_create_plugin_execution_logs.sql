@@ -0,0 +1,12 @@
+-- Output lines streamed by plugin executions
+CREATE TABLE plugin_execution_logs (
+    id BIGSERIAL PRIMARY KEY,
+    execution_id UUID NOT NULL REFERENCES plugin_executions(id) ON DELETE CASCADE,
+    seq BIGINT NOT NULL,
+    stream TEXT NOT NULL CHECK (stream IN ('stdout', 'stderr')),
+    logged_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    line TEXT NOT NULL,
+    UNIQUE (execution_id, seq)
+);
+
+CREATE INDEX idx_plugin_execution_logs_logged_at ON plugin_execution_logs(logged_at);
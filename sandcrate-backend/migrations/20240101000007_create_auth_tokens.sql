@@ -0,0 +1,29 @@
+-- Rotating refresh tokens; only a SHA-256 hash of each token is stored
+CREATE TABLE refresh_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- Every token rotated from the same login shares a family, revoked together on reuse
+    family_id UUID NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    -- The access token issued alongside, so revoking the family also revokes it
+    access_jti UUID NOT NULL,
+    access_expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    replaced_by UUID REFERENCES refresh_tokens(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_refresh_tokens_family_id ON refresh_tokens(family_id);
+CREATE INDEX idx_refresh_tokens_username ON refresh_tokens(username);
+CREATE INDEX idx_refresh_tokens_expires_at ON refresh_tokens(expires_at);
+
+-- Access tokens revoked before they expire, checked by jti on every request
+CREATE TABLE revoked_tokens (
+    jti UUID PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_revoked_tokens_expires_at ON revoked_tokens(expires_at);
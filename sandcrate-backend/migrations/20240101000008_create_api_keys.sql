@@ -0,0 +1,17 @@
+-- Personal API keys for scripted access; only a SHA-256 hash of each key is stored
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    -- First characters of the key, shown in listings so users can tell keys apart
+    key_prefix VARCHAR(32) NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- Empty means the key may do everything its owner can
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_api_keys_username ON api_keys(username);
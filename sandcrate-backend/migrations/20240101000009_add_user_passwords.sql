@@ -0,0 +1,2 @@
+-- Argon2 hashes for the database authentication provider; NULL for PAM and LDAP accounts
+ALTER TABLE users ADD COLUMN password_hash TEXT;
@@ -0,0 +1,10 @@
+-- The initial schema seeded an `admin` account with the admin role, so whoever could log in as
+-- `admin` through PAM or LDAP became a Sandcrate admin. Drop the row if it was never used and
+-- another active admin exists; an account that did log in is left as it is, and so is a seeded
+-- row that is still the only admin, so no deployment is left without one.
+DELETE FROM users
+WHERE username = 'admin' AND role = 'admin' AND last_login_at IS NULL AND password_hash IS NULL
+  AND EXISTS (
+      SELECT 1 FROM users other
+      WHERE other.username <> 'admin' AND other.role = 'admin' AND other.is_active
+  );
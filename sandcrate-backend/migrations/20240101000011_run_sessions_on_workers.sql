@@ -0,0 +1,18 @@
+-- Executions run on workers, so the API reads their whole outcome back from the row
+ALTER TABLE plugin_executions ADD COLUMN stderr TEXT;
+ALTER TABLE plugin_executions ADD COLUMN exit_code INTEGER;
+ALTER TABLE plugin_executions ADD COLUMN output_truncated BOOLEAN NOT NULL DEFAULT false;
+
+-- Jobs started from a WebSocket session read stdin sent by the client
+ALTER TABLE plugin_jobs ADD COLUMN session_id VARCHAR(255);
+ALTER TABLE plugin_jobs ADD COLUMN stdin_closed BOOLEAN NOT NULL DEFAULT false;
+
+-- Stdin chunks not yet handed to the plugin; the worker deletes each one once delivered
+CREATE TABLE plugin_job_input (
+    id BIGSERIAL PRIMARY KEY,
+    job_id UUID NOT NULL REFERENCES plugin_jobs(id) ON DELETE CASCADE,
+    data BYTEA NOT NULL,
+    eof BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE INDEX idx_plugin_job_input_job_id ON plugin_job_input(job_id, id);
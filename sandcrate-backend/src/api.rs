@@ -1,6 +1,6 @@
 use axum::{
     routing::{get, post, delete},
-    Json, Router, extract::{State, Path, Multipart},
+    Json, Router, extract::{DefaultBodyLimit, State, Path, Multipart, Query},
     http::StatusCode,
     response::{IntoResponse, Response},
 };
@@ -11,10 +11,28 @@ use axum_extra::{
 use serde::{Serialize, Deserialize};
 use std::sync::Arc;
 use std::fs;
-use std::path::Path as FsPath;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
 
-use crate::auth::{AuthConfig, validate_token};
+use crate::auth::{ApiScope, AuthConfig, UserInfo, validate_token};
+use crate::database::{
+    CreateJobRequest, CreatePluginRequest, ExecutionCursor, ExecutionHistoryFilter, ExecutionLogFilter,
+    ExecutionLogLine, ExecutionStatus, LogStream, Plugin as PluginRecord, PluginExecution, PluginJob,
+};
+use crate::jobs::{JobQueue, JobQueueError};
 use crate::plugin;
+use crate::services::PluginService;
+
+type ApiState = (Arc<AuthConfig>, Arc<PluginService>, Arc<JobQueue>);
+
+const MAX_JOB_WAIT_MS: u64 = 30_000;
+
+// How long `/execute` waits for a free worker on top of the execution timeout before it hands
+// the client the queued job to poll instead.
+const EXECUTE_QUEUE_WAIT_MS: u64 = 30_000;
+
+// Room for the multipart framing and metadata fields around the plugin file itself.
+const UPLOAD_OVERHEAD_BYTES: usize = 1024 * 1024;
 
 #[derive(Serialize)]
 struct Plugin {
@@ -24,6 +42,34 @@ struct Plugin {
     size: u64,
     created_at: String,
     status: String,
+    description: Option<String>,
+    version: String,
+    author: Option<String>,
+    tags: Vec<String>,
+    execution_count: i32,
+    average_execution_time_ms: Option<i64>,
+    last_executed_at: Option<String>,
+}
+
+impl From<PluginRecord> for Plugin {
+    fn from(plugin: PluginRecord) -> Self {
+        Self {
+            id: plugin.id.to_string(),
+            name: plugin.name,
+            filename: plugin.filename,
+            size: plugin.file_size as u64,
+            created_at: plugin.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            status: plugin.status.as_str().to_string(),
+            description: plugin.description,
+            version: plugin.version,
+            author: plugin.author,
+            tags: plugin.tags,
+            execution_count: plugin.execution_count,
+            average_execution_time_ms: plugin.average_execution_time_ms,
+            last_executed_at: plugin.last_executed_at
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -39,12 +85,160 @@ struct PluginExecutionRequest {
 
 #[derive(Serialize)]
 struct PluginExecutionResponse {
+    execution_id: String,
     success: bool,
+    status: String,
     result: String,
+    stderr: String,
+    exit_code: Option<i32>,
+    truncated: bool,
+    peak_memory_bytes: u64,
     execution_time_ms: u64,
     error: Option<String>,
 }
 
+impl From<PluginExecution> for PluginExecutionResponse {
+    fn from(execution: PluginExecution) -> Self {
+        Self {
+            execution_id: execution.id.to_string(),
+            success: execution.status == ExecutionStatus::Completed,
+            status: execution.status.as_str().to_string(),
+            result: execution.result.unwrap_or_default(),
+            stderr: execution.stderr.unwrap_or_default(),
+            exit_code: execution.exit_code,
+            truncated: execution.output_truncated,
+            peak_memory_bytes: execution.peak_memory_bytes.unwrap_or(0) as u64,
+            execution_time_ms: execution.execution_time_ms as u64,
+            error: execution.error,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Execution {
+    id: String,
+    plugin_id: String,
+    user_id: Option<String>,
+    session_id: Option<String>,
+    status: String,
+    parameters: Option<serde_json::Value>,
+    result: Option<String>,
+    stderr: Option<String>,
+    exit_code: Option<i32>,
+    truncated: bool,
+    error: Option<String>,
+    execution_time_ms: i64,
+    peak_memory_bytes: Option<i64>,
+    started_at: String,
+    completed_at: Option<String>,
+}
+
+impl From<PluginExecution> for Execution {
+    fn from(execution: PluginExecution) -> Self {
+        Self {
+            id: execution.id.to_string(),
+            plugin_id: execution.plugin_id.to_string(),
+            user_id: execution.user_id.map(|id| id.to_string()),
+            session_id: execution.session_id,
+            status: execution.status.as_str().to_string(),
+            parameters: execution.parameters,
+            result: execution.result,
+            stderr: execution.stderr,
+            exit_code: execution.exit_code,
+            truncated: execution.output_truncated,
+            error: execution.error,
+            execution_time_ms: execution.execution_time_ms,
+            peak_memory_bytes: execution.peak_memory_bytes,
+            started_at: execution.started_at.to_rfc3339(),
+            completed_at: execution.completed_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Job {
+    id: String,
+    plugin_id: String,
+    status: String,
+    attempts: i32,
+    error: Option<String>,
+    execution: Option<Execution>,
+    created_at: String,
+    started_at: Option<String>,
+    completed_at: Option<String>,
+}
+
+impl Job {
+    fn new(job: PluginJob, execution: Option<PluginExecution>) -> Self {
+        Self {
+            id: job.id.to_string(),
+            plugin_id: job.plugin_id.to_string(),
+            status: job.status.as_str().to_string(),
+            attempts: job.attempts,
+            error: job.error,
+            execution: execution.map(Execution::from),
+            created_at: job.created_at.to_rfc3339(),
+            started_at: job.started_at.map(|t| t.to_rfc3339()),
+            completed_at: job.completed_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JobQuery {
+    wait_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ExecutionList {
+    executions: Vec<Execution>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExecutionHistoryQuery {
+    status: Option<ExecutionStatus>,
+    user_id: Option<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct LogLine {
+    seq: i64,
+    stream: String,
+    logged_at: String,
+    line: String,
+}
+
+impl From<ExecutionLogLine> for LogLine {
+    fn from(line: ExecutionLogLine) -> Self {
+        Self {
+            seq: line.seq,
+            stream: line.stream,
+            logged_at: line.logged_at.to_rfc3339(),
+            line: line.line,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExecutionLogPage {
+    lines: Vec<LogLine>,
+    next_seq: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ExecutionLogQuery {
+    stream: Option<LogStream>,
+    after_seq: Option<i64>,
+    limit: Option<i64>,
+}
+
+const MAX_LOG_PAGE: i64 = 5000;
+
 #[derive(Serialize)]
 struct ApiResponse<T> {
     success: bool,
@@ -52,13 +246,56 @@ struct ApiResponse<T> {
     error: Option<String>,
 }
 
+fn error_response<T>(status: StatusCode, message: impl Into<String>) -> (StatusCode, Json<ApiResponse<T>>) {
+    (
+        status,
+        Json(ApiResponse {
+            success: false,
+            data: None,
+            error: Some(message.into()),
+        })
+    )
+}
+
+fn require_scope<T>(user: &UserInfo, scope: ApiScope) -> Result<(), (StatusCode, Json<ApiResponse<T>>)> {
+    user.authorize(scope)
+        .map_err(|message| error_response(StatusCode::FORBIDDEN, message))
+}
+
+fn enqueue_failed<T>(plugin_id: &str, e: JobQueueError) -> (StatusCode, Json<ApiResponse<T>>) {
+    match e {
+        JobQueueError::QueueFull(_) => error_response(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        JobQueueError::Database(e) => {
+            eprintln!("Failed to enqueue job for plugin {}: {}", plugin_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to enqueue job")
+        }
+    }
+}
+
+async fn find_plugin<T>(
+    plugin_service: &PluginService,
+    plugin_id: &str,
+) -> Result<PluginRecord, (StatusCode, Json<ApiResponse<T>>)> {
+    let id = Uuid::parse_str(plugin_id)
+        .map_err(|_| error_response(StatusCode::NOT_FOUND, format!("Plugin '{}' not found", plugin_id)))?;
+
+    match plugin_service.get_plugin_by_id(id).await {
+        Ok(Some(plugin)) => Ok(plugin),
+        Ok(None) => Err(error_response(StatusCode::NOT_FOUND, format!("Plugin '{}' not found", plugin_id))),
+        Err(e) => {
+            eprintln!("Failed to load plugin {}: {}", plugin_id, e);
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load plugin"))
+        }
+    }
+}
+
 
 
 async fn get_plugins(
-    State(config): State<Arc<AuthConfig>>,
+    State((config, plugin_service, _)): State<ApiState>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
 ) -> Result<Json<ApiResponse<PluginList>>, (StatusCode, Json<ApiResponse<PluginList>>)> {
-    let _user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
         .map_err(|_| {
             (
                 StatusCode::UNAUTHORIZED,
@@ -69,63 +306,29 @@ async fn get_plugins(
                 })
             )
         })?;
-    let plugins_dir = FsPath::new("../assets/plugins");
-    let mut plugins = Vec::new();
-    
-    if let Ok(entries) = fs::read_dir(plugins_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    if extension == "wasm" {
-                        if let Ok(metadata) = fs::metadata(&path) {
-                            let filename = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-                            
-                            let name = filename.replace(".wasm", "");
-                            let id = name.clone();
-                            
-                            let created_at = metadata.created()
-                                .ok()
-                                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
-                                .map(|duration| {
-                                    chrono::DateTime::from_timestamp(duration.as_secs() as i64, 0)
-                                        .unwrap_or_default()
-                                        .format("%Y-%m-%d %H:%M:%S")
-                                        .to_string()
-                                })
-                                .unwrap_or_else(|| "Unknown".to_string());
-                            
-                            plugins.push(Plugin {
-                                id,
-                                name,
-                                filename,
-                                size: metadata.len(),
-                                created_at,
-                                status: "ready".to_string(),
-                            });
-                        }
-                    }
-                }
-            }
-        }
-    }
+    require_scope(&user, ApiScope::PluginsRead)?;
+
+    let plugins = plugin_service.list_plugins(None, None).await
+        .map_err(|e| {
+            eprintln!("Failed to list plugins: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list plugins")
+        })?;
     
     Ok(Json(ApiResponse {
         success: true,
-        data: Some(PluginList { plugins }),
+        data: Some(PluginList {
+            plugins: plugins.into_iter().map(Plugin::from).collect(),
+        }),
         error: None,
     }))
 }
 
 async fn get_plugin(
-    State(config): State<Arc<AuthConfig>>,
+    State((config, plugin_service, _)): State<ApiState>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     Path(plugin_id): Path<String>,
-) -> Result<Response, (StatusCode, Json<ApiResponse<Plugin>>)> {
-    let _user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+) -> Result<Json<ApiResponse<Plugin>>, (StatusCode, Json<ApiResponse<Plugin>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
         .map_err(|_| {
             (
                 StatusCode::UNAUTHORIZED,
@@ -136,76 +339,24 @@ async fn get_plugin(
                 })
             )
         })?;
-    let plugins_dir = FsPath::new("../assets/plugins");
-    let plugin_path = plugins_dir.join(format!("{}.wasm", plugin_id));
-    
-    if !plugin_path.exists() {
-        return Ok((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<Plugin> {
-                success: false,
-                data: None,
-                error: Some(format!("Plugin '{}' not found", plugin_id)),
-            })
-        ).into_response());
-    }
+    require_scope(&user, ApiScope::PluginsRead)?;
+
+    let plugin = find_plugin(&plugin_service, &plugin_id).await?;
     
-    if let Ok(metadata) = fs::metadata(&plugin_path) {
-        let filename = plugin_path.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown")
-            .to_string();
-        
-        let name = filename.replace(".wasm", "");
-        let id = name.clone();
-        
-        let created_at = metadata.created()
-            .ok()
-            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|duration| {
-                chrono::DateTime::from_timestamp(duration.as_secs() as i64, 0)
-                    .unwrap_or_default()
-                    .format("%Y-%m-%d %H:%M:%S")
-                    .to_string()
-            })
-            .unwrap_or_else(|| "Unknown".to_string());
-        
-        let plugin = Plugin {
-            id,
-            name,
-            filename,
-            size: metadata.len(),
-            created_at,
-            status: "ready".to_string(),
-        };
-        
-        Ok((
-            StatusCode::OK,
-            Json(ApiResponse {
-                success: true,
-                data: Some(plugin),
-                error: None,
-            })
-        ).into_response())
-    } else {
-        Ok((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<Plugin> {
-                success: false,
-                data: None,
-                error: Some("Failed to read plugin metadata".to_string()),
-            })
-        ).into_response())
-    }
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(Plugin::from(plugin)),
+        error: None,
+    }))
 }
 
 async fn execute_plugin(
-    State(config): State<Arc<AuthConfig>>,
+    State((config, plugin_service, job_queue)): State<ApiState>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     Path(plugin_id): Path<String>,
     Json(request): Json<PluginExecutionRequest>,
 ) -> Result<Response, (StatusCode, Json<ApiResponse<PluginExecutionResponse>>)> {
-    let _user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
         .map_err(|_| {
             (
                 StatusCode::UNAUTHORIZED,
@@ -216,79 +367,89 @@ async fn execute_plugin(
                 })
             )
         })?;
-    let start_time = std::time::Instant::now();
+    require_scope(&user, ApiScope::PluginsExecute)?;
+    plugin_service
+        .check_timeout(request.timeout)
+        .map_err(|message| error_response(StatusCode::BAD_REQUEST, message))?;
+    let plugin = find_plugin(&plugin_service, &plugin_id).await?;
+    
+    // The plugin runs on a worker like any queued job; this request only waits for it.
+    let job = job_queue
+        .enqueue(CreateJobRequest {
+            plugin_id: plugin.id,
+            user_id: user.user_id(),
+            parameters: request.parameters,
+            timeout_ms: request.timeout.map(|t| t as i64),
+            session_id: None,
+        })
+        .await
+        .map_err(|e| enqueue_failed(&plugin_id, e))?;
     
-    let plugins_dir = FsPath::new("../assets/plugins");
-    let plugin_path = plugins_dir.join(format!("{}.wasm", plugin_id));
+    let job_id = job.id;
+    let load_failed = |e: sqlx::Error| {
+        eprintln!("Failed to load job {}: {}", job_id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load job")
+    };
+    let wait = std::time::Duration::from_millis(request.timeout.unwrap_or(plugin::DEFAULT_TIMEOUT_MS) + EXECUTE_QUEUE_WAIT_MS);
+    let job = job_queue
+        .wait_for_job(job_id, wait)
+        .await
+        .map_err(load_failed)?
+        .ok_or_else(|| error_response(StatusCode::INTERNAL_SERVER_ERROR, "Job disappeared"))?;
     
-    if !plugin_path.exists() {
+    if !job.status.is_finished() {
         return Ok((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<PluginExecutionResponse> {
-                success: false,
-                data: None,
-                error: Some(format!("Plugin '{}' not found", plugin_id)),
+            StatusCode::ACCEPTED,
+            Json(ApiResponse {
+                success: true,
+                data: Some(Job::new(job, None)),
+                error: None,
             })
         ).into_response());
     }
     
-    let parameters = request.parameters;
-    let timeout = request.timeout;
-    
-    let plugin_path_str = plugin_path.to_str().unwrap_or("");
-    let execution_result = plugin::run_plugin_with_params(
-        plugin_path_str,
-        parameters,
-        timeout
-    );
+    let execution = match job.execution_id {
+        Some(execution_id) => plugin_service.get_execution(execution_id).await.map_err(|e| {
+            eprintln!("Failed to load execution {}: {}", execution_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load execution")
+        })?,
+        None => None,
+    };
+    // Without an execution the job failed before the plugin ran, e.g. because it was deleted.
+    let Some(execution) = execution else {
+        let error = job.error.unwrap_or_else(|| "Execution failed".to_string());
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, error));
+    };
     
-    let execution_time = start_time.elapsed();
-    let execution_time_ms = execution_time.as_millis() as u64;
+    let status_code = match execution.status {
+        ExecutionStatus::Completed => StatusCode::OK,
+        // The plugin ran to completion and reported failure through its exit code.
+        ExecutionStatus::Failed if execution.exit_code.is_some() => StatusCode::OK,
+        ExecutionStatus::Failed => StatusCode::UNPROCESSABLE_ENTITY,
+        ExecutionStatus::Timeout => StatusCode::REQUEST_TIMEOUT,
+        ExecutionStatus::Cancelled => StatusCode::CONFLICT,
+        ExecutionStatus::Running => StatusCode::INTERNAL_SERVER_ERROR,
+    };
     
-            match execution_result {
-            Ok(result) => {
-                let response = PluginExecutionResponse {
-                    success: true,
-                    result,
-                    execution_time_ms,
-                    error: None,
-                };
-                
-                Ok((
-                    StatusCode::OK,
-                    Json(ApiResponse {
-                        success: true,
-                        data: Some(response),
-                        error: None,
-                    })
-                ).into_response())
-            }
-            Err(e) => {
-                let response = PluginExecutionResponse {
-                    success: false,
-                    result: String::new(),
-                    execution_time_ms,
-                    error: Some(e.to_string()),
-                };
-                
-                Ok((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: true,
-                        data: Some(response),
-                        error: None,
-                    })
-                ).into_response())
-            }
-        }
+    // The execution is returned either way, with the output written before a timeout or cancellation.
+    let success = status_code.is_success();
+    let error = if success { None } else { execution.error.clone() };
+    Ok((
+        status_code,
+        Json(ApiResponse {
+            success,
+            data: Some(PluginExecutionResponse::from(execution)),
+            error,
+        })
+    ).into_response())
 }
 
 async fn upload_plugin(
-    State(config): State<Arc<AuthConfig>>,
+    State((config, plugin_service, _)): State<ApiState>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     mut multipart: Multipart,
-) -> Result<Json<ApiResponse<String>>, (StatusCode, Json<ApiResponse<String>>)> {
-    let _user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+) -> Result<Json<ApiResponse<Plugin>>, (StatusCode, Json<ApiResponse<Plugin>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
         .map_err(|_| {
             (
                 StatusCode::UNAUTHORIZED,
@@ -299,68 +460,105 @@ async fn upload_plugin(
                 })
             )
         })?;
+    require_scope(&user, ApiScope::PluginsWrite)?;
+
+    let mut upload = None;
+    let mut name = None;
+    let mut description = None;
+    let mut version = None;
+    let mut author = None;
+    let mut tags = Vec::new();
 
     while let Some(field) = multipart.next_field().await.map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                error: Some("Failed to read multipart data".to_string()),
-            })
-        )
+        error_response(StatusCode::BAD_REQUEST, "Failed to read multipart data")
     })? {
-        let name = field.name().unwrap_or("").to_string();
-        if name == "plugin" {
+        let field_name = field.name().unwrap_or("").to_string();
+        if field_name == "plugin" {
+            let original_name = field.file_name().map(|n| n.to_string());
             let data = field.bytes().await.map_err(|_| {
-                (
-                    StatusCode::BAD_REQUEST,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        error: Some("Failed to read plugin file".to_string()),
-                    })
-                )
+                error_response(StatusCode::BAD_REQUEST, "Failed to read plugin file")
             })?;
-            
-            let filename = format!("plugin_{}.wasm", uuid::Uuid::new_v4());
-            let plugin_path = FsPath::new("../assets/plugins").join(&filename);
-            
-            if let Err(_) = fs::write(&plugin_path, data) {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        error: Some("Failed to save plugin file".to_string()),
-                    })
+            if data.len() > plugin_service.max_plugin_size() {
+                return Err(error_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Plugin exceeds the maximum size of {} bytes", plugin_service.max_plugin_size()),
                 ));
             }
-            
-            return Ok(Json(ApiResponse {
-                success: true,
-                data: Some(filename),
-                error: None,
-            }));
+            upload = Some((original_name, data));
+            continue;
+        }
+
+        let value = field.text().await.map_err(|_| {
+            error_response(StatusCode::BAD_REQUEST, format!("Failed to read field '{}'", field_name))
+        })?;
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+
+        match field_name.as_str() {
+            "name" => name = Some(value),
+            "description" => description = Some(value),
+            "version" => version = Some(value),
+            "author" => author = Some(value),
+            "tags" => tags = value.split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            _ => {}
         }
     }
+
+    let (original_name, data) = upload
+        .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "No plugin file found in request"))?;
+
+    if !data.starts_with(b"\0asm") {
+        return Err(error_response(StatusCode::BAD_REQUEST, "Uploaded file is not a WebAssembly module"));
+    }
+
+    let filename = format!("plugin_{}.wasm", Uuid::new_v4());
+    let plugin_path = plugin_service.plugins_dir().join(&filename);
     
-    Err((
-        StatusCode::BAD_REQUEST,
-        Json(ApiResponse {
-            success: false,
-            data: None,
-            error: Some("No plugin file found in request".to_string()),
-        })
-    ))
+    if fs::write(&plugin_path, &data).is_err() {
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save plugin file"));
+    }
+
+    let name = name
+        .or_else(|| original_name.map(|n| n.trim_end_matches(".wasm").to_string()))
+        .unwrap_or_else(|| filename.trim_end_matches(".wasm").to_string());
+
+    let request = CreatePluginRequest {
+        name,
+        filename: filename.clone(),
+        file_path: plugin_path.to_string_lossy().to_string(),
+        file_size: data.len() as i64,
+        description,
+        version: version.unwrap_or_else(|| "1.0.0".to_string()),
+        author: author.or_else(|| Some(user.username.clone())),
+        tags,
+    };
+
+    // The row and the file are created together: if the insert fails, the file goes too.
+    match plugin_service.create_plugin(request).await {
+        Ok(plugin) => Ok(Json(ApiResponse {
+            success: true,
+            data: Some(Plugin::from(plugin)),
+            error: None,
+        })),
+        Err(e) => {
+            eprintln!("Failed to create plugin record for {}: {}", filename, e);
+            let _ = fs::remove_file(&plugin_path);
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save plugin"))
+        }
+    }
 }
 
 async fn delete_plugin(
-    State(config): State<Arc<AuthConfig>>,
+    State((config, plugin_service, _)): State<ApiState>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     Path(plugin_id): Path<String>,
 ) -> Result<Json<ApiResponse<String>>, (StatusCode, Json<ApiResponse<String>>)> {
-    let _user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
         .map_err(|_| {
             (
                 StatusCode::UNAUTHORIZED,
@@ -371,44 +569,440 @@ async fn delete_plugin(
                 })
             )
         })?;
+    require_scope(&user, ApiScope::PluginsWrite)?;
 
-    let plugins_dir = FsPath::new("../assets/plugins");
-    let plugin_path = plugins_dir.join(format!("{}.wasm", plugin_id));
+    let plugin = find_plugin(&plugin_service, &plugin_id).await?;
     
-    if !plugin_path.exists() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                error: Some(format!("Plugin '{}' not found", plugin_id)),
-            })
-        ));
+    match plugin_service.delete_plugin(plugin.id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(error_response(StatusCode::NOT_FOUND, format!("Plugin '{}' not found", plugin_id)));
+        }
+        Err(e) => {
+            eprintln!("Failed to delete plugin {}: {}", plugin_id, e);
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete plugin"));
+        }
     }
     
-    if let Err(_) = fs::remove_file(&plugin_path) {
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                error: Some("Failed to delete plugin file".to_string()),
-            })
-        ));
+    if let Err(e) = fs::remove_file(&plugin.file_path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Failed to delete plugin file {}: {}", plugin.file_path, e);
+        }
     }
     
     Ok(Json(ApiResponse {
         success: true,
-        data: Some(format!("Plugin '{}' deleted successfully", plugin_id)),
+        data: Some(format!("Plugin '{}' deleted successfully", plugin.name)),
+        error: None,
+    }))
+}
+
+async fn get_plugin_executions(
+    State((config, plugin_service, _)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(plugin_id): Path<String>,
+    Query(query): Query<ExecutionHistoryQuery>,
+) -> Result<Json<ApiResponse<ExecutionList>>, (StatusCode, Json<ApiResponse<ExecutionList>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsRead)?;
+
+    let plugin = find_plugin(&plugin_service, &plugin_id).await?;
+
+    let cursor = match query.cursor.as_deref() {
+        Some(cursor) => Some(ExecutionCursor::decode(cursor)
+            .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "Invalid cursor"))?),
+        None => None,
+    };
+
+    // Only admins may look at other users' runs; a nil id matches nothing.
+    let user_id = if user.is_admin {
+        query.user_id
+    } else {
+        Some(user.user_id().unwrap_or_default())
+    };
+
+    let filter = ExecutionHistoryFilter {
+        status: query.status,
+        user_id,
+        started_after: query.from,
+        started_before: query.to,
+        cursor,
+        limit: query.limit,
+    };
+
+    let page = plugin_service.get_execution_history(plugin.id, filter).await
+        .map_err(|e| {
+            eprintln!("Failed to load execution history for plugin {}: {}", plugin_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load execution history")
+        })?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(ExecutionList {
+            executions: page.executions.into_iter().map(Execution::from).collect(),
+            next_cursor: page.next_cursor,
+        }),
+        error: None,
+    }))
+}
+
+async fn get_execution(
+    State((config, plugin_service, _)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(execution_id): Path<String>,
+) -> Result<Json<ApiResponse<Execution>>, (StatusCode, Json<ApiResponse<Execution>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsRead)?;
+    let execution = find_execution(&plugin_service, &execution_id).await?;
+    require_owner(&user, &execution)?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(Execution::from(execution)),
         error: None,
     }))
 }
 
-pub fn routes() -> Router<Arc<AuthConfig>> {
+// Other users' executions are reported as missing rather than forbidden so ids cannot be probed.
+fn require_owner<T>(user: &UserInfo, execution: &PluginExecution) -> Result<(), (StatusCode, Json<ApiResponse<T>>)> {
+    if user.can_access(execution.user_id) {
+        Ok(())
+    } else {
+        Err(error_response(StatusCode::NOT_FOUND, format!("Execution '{}' not found", execution.id)))
+    }
+}
+
+async fn find_execution<T>(
+    plugin_service: &PluginService,
+    execution_id: &str,
+) -> Result<PluginExecution, (StatusCode, Json<ApiResponse<T>>)> {
+    let not_found = || error_response(StatusCode::NOT_FOUND, format!("Execution '{}' not found", execution_id));
+    let id = Uuid::parse_str(execution_id).map_err(|_| not_found())?;
+
+    match plugin_service.get_execution(id).await {
+        Ok(Some(execution)) => Ok(execution),
+        Ok(None) => Err(not_found()),
+        Err(e) => {
+            eprintln!("Failed to load execution {}: {}", execution_id, e);
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load execution"))
+        }
+    }
+}
+
+async fn get_execution_logs(
+    State((config, plugin_service, _)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(execution_id): Path<String>,
+    Query(query): Query<ExecutionLogQuery>,
+) -> Result<Json<ApiResponse<ExecutionLogPage>>, (StatusCode, Json<ApiResponse<ExecutionLogPage>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsRead)?;
+    let execution = find_execution(&plugin_service, &execution_id).await?;
+    require_owner(&user, &execution)?;
+
+    let limit = query.limit.unwrap_or(500).clamp(1, MAX_LOG_PAGE);
+    let lines = plugin_service
+        .get_execution_logs(execution.id, ExecutionLogFilter {
+            stream: query.stream,
+            after_seq: query.after_seq,
+            limit: Some(limit),
+        })
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to load logs of execution {}: {}", execution_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load execution logs")
+        })?;
+
+    let next_seq = if lines.len() as i64 == limit {
+        lines.last().map(|line| line.seq)
+    } else {
+        None
+    };
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(ExecutionLogPage {
+            lines: lines.into_iter().map(LogLine::from).collect(),
+            next_seq,
+        }),
+        error: None,
+    }))
+}
+
+async fn download_execution_logs(
+    State((config, plugin_service, _)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(execution_id): Path<String>,
+    Query(query): Query<ExecutionLogQuery>,
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsRead)?;
+    let execution = find_execution(&plugin_service, &execution_id).await?;
+    require_owner(&user, &execution)?;
+
+    // One page is read from the database per chunk sent, so a long log is never held in memory.
+    // Once the response has started an error can only cut it short, so it is logged here.
+    let stream = query.stream;
+    let id = execution.id;
+    let pages = futures_util::stream::try_unfold(Some(None), move |cursor: Option<Option<i64>>| {
+        let plugin_service = plugin_service.clone();
+        async move {
+            let Some(after_seq) = cursor else {
+                return Ok::<_, axum::BoxError>(None);
+            };
+            let lines = plugin_service
+                .get_execution_logs(id, ExecutionLogFilter {
+                    stream,
+                    after_seq,
+                    limit: Some(MAX_LOG_PAGE),
+                })
+                .await
+                .inspect_err(|e| eprintln!("Failed to load logs of execution {}: {}", id, e))?;
+
+            let next = (lines.len() as i64 == MAX_LOG_PAGE).then(|| lines.last().map(|line| line.seq));
+            let mut chunk = String::new();
+            for line in lines {
+                chunk.push_str(&format!("{} [{}] {}\n", line.logged_at.to_rfc3339(), line.stream, line.line));
+            }
+            Ok(Some((chunk, next)))
+        }
+    });
+
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"execution-{}.log\"", execution.id),
+            ),
+        ],
+        axum::body::Body::from_stream(pages),
+    ).into_response())
+}
+
+async fn cancel_execution(
+    State((config, plugin_service, _)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(execution_id): Path<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Execution>>), (StatusCode, Json<ApiResponse<Execution>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsExecute)?;
+    let execution = find_execution(&plugin_service, &execution_id).await?;
+    require_owner(&user, &execution)?;
+
+    if execution.status != ExecutionStatus::Running {
+        return Err(error_response(
+            StatusCode::CONFLICT,
+            format!("Execution '{}' is already {}", execution_id, execution.status.as_str()),
+        ));
+    }
+
+    // The process running the plugin records the cancelled outcome once the guest has stopped.
+    if let Err(e) = plugin_service.request_cancellation(execution.id).await {
+        eprintln!("Failed to cancel execution {}: {}", execution_id, e);
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to cancel execution"));
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse {
+            success: true,
+            data: Some(Execution::from(execution)),
+            error: None,
+        })
+    ))
+}
+
+async fn get_runtime_stats(
+    State((config, _, _)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<ApiResponse<plugin::RuntimeStats>>, (StatusCode, Json<ApiResponse<plugin::RuntimeStats>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsRead)?;
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(plugin::runtime().stats()),
+        error: None,
+    }))
+}
+
+async fn enqueue_job(
+    State((config, plugin_service, job_queue)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(plugin_id): Path<String>,
+    Json(request): Json<PluginExecutionRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Job>>), (StatusCode, Json<ApiResponse<Job>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsExecute)?;
+    plugin_service
+        .check_timeout(request.timeout)
+        .map_err(|message| error_response(StatusCode::BAD_REQUEST, message))?;
+    let plugin = find_plugin(&plugin_service, &plugin_id).await?;
+
+    let job = job_queue
+        .enqueue(CreateJobRequest {
+            plugin_id: plugin.id,
+            user_id: user.user_id(),
+            parameters: request.parameters,
+            timeout_ms: request.timeout.map(|t| t as i64),
+            session_id: None,
+        })
+        .await
+        .map_err(|e| enqueue_failed(&plugin_id, e))?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse {
+            success: true,
+            data: Some(Job::new(job, None)),
+            error: None,
+        })
+    ))
+}
+
+async fn get_job(
+    State((config, plugin_service, job_queue)): State<ApiState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(job_id): Path<String>,
+    Query(query): Query<JobQuery>,
+) -> Result<Json<ApiResponse<Job>>, (StatusCode, Json<ApiResponse<Job>>)> {
+    let user = validate_token(State(config.clone()), TypedHeader(Authorization(bearer))).await
+        .map_err(|_| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse {
+                    success: false,
+                    data: None,
+                    error: Some("Invalid or missing authentication token".to_string()),
+                })
+            )
+        })?;
+    require_scope(&user, ApiScope::PluginsRead)?;
+
+    let not_found = || error_response(StatusCode::NOT_FOUND, format!("Job '{}' not found", job_id));
+    let id = Uuid::parse_str(&job_id).map_err(|_| not_found())?;
+
+    let load_failed = |e: sqlx::Error| {
+        eprintln!("Failed to load job {}: {}", job_id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load job")
+    };
+
+    // Checked before waiting so other users' job ids cannot be probed or used to hold requests open.
+    let job = match job_queue.get_job(id).await.map_err(load_failed)? {
+        Some(job) if user.can_access(job.user_id) => job,
+        _ => return Err(not_found()),
+    };
+
+    let job = match query.wait_ms {
+        Some(wait_ms) if !job.status.is_finished() => {
+            let wait = std::time::Duration::from_millis(wait_ms.min(MAX_JOB_WAIT_MS));
+            job_queue.wait_for_job(id, wait).await.map_err(load_failed)?.ok_or_else(not_found)?
+        }
+        _ => job,
+    };
+
+    let execution = match job.execution_id {
+        Some(execution_id) => plugin_service.get_execution(execution_id).await.unwrap_or_else(|e| {
+            eprintln!("Failed to load execution {}: {}", execution_id, e);
+            None
+        }),
+        None => None,
+    };
+
+    Ok(Json(ApiResponse {
+        success: true,
+        data: Some(Job::new(job, execution)),
+        error: None,
+    }))
+}
+
+pub fn routes(max_plugin_size: usize) -> Router<ApiState> {
     Router::new()
         .route("/plugins", get(get_plugins))
-        .route("/plugins/upload", post(upload_plugin))
+        .route(
+            "/plugins/upload",
+            post(upload_plugin).layer(DefaultBodyLimit::max(max_plugin_size + UPLOAD_OVERHEAD_BYTES)),
+        )
         .route("/plugins/:id", get(get_plugin))
         .route("/plugins/:id", delete(delete_plugin))
         .route("/plugins/:id/execute", post(execute_plugin))
+        .route("/plugins/:id/executions", get(get_plugin_executions))
+        .route("/plugins/:id/jobs", post(enqueue_job))
+        .route("/executions/:id", get(get_execution))
+        .route("/executions/:id/cancel", post(cancel_execution))
+        .route("/executions/:id/logs", get(get_execution_logs))
+        .route("/executions/:id/logs/download", get(download_execution_logs))
+        .route("/jobs/:id", get(get_job))
+        .route("/runtime/stats", get(get_runtime_stats))
 }
@@ -1,25 +1,76 @@
 use axum::{
-    extract::State,
+    extract::{Path, State},
     http::StatusCode,
     response::Json,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use axum_extra::{
     extract::TypedHeader,
     headers::{authorization::Bearer, Authorization},
 };
-use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use pam::Authenticator;
+use chrono::{Duration, TimeZone, Utc};
+use jsonwebtoken::{decode, encode, errors::ErrorKind, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
 use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth_provider::{hash_password, AuthProvider, MIN_PASSWORD_LEN};
+use crate::database::{ApiKey, NewApiKey, NewRefreshToken, TokenRepository, User, UserRepository, UserRole};
+
+// Bearer credentials starting with this are API keys rather than JWTs.
+pub const API_KEY_PREFIX: &str = "sck_";
+
+// Characters of a key kept in plain text so listings can show which key is which.
+const API_KEY_DISPLAY_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
+pub enum ApiScope {
+    #[serde(rename = "plugins:read")]
+    PluginsRead,
+    #[serde(rename = "plugins:write")]
+    PluginsWrite,
+    #[serde(rename = "plugins:execute")]
+    PluginsExecute,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PluginsRead => "plugins:read",
+            ApiScope::PluginsWrite => "plugins:write",
+            ApiScope::PluginsExecute => "plugins:execute",
+        }
+    }
+
+    fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "plugins:read" => Some(ApiScope::PluginsRead),
+            "plugins:write" => Some(ApiScope::PluginsWrite),
+            "plugins:execute" => Some(ApiScope::PluginsExecute),
+            _ => None,
+        }
+    }
+}
+
+// Guests can browse, users can also run plugins, only admins can change what is installed.
+fn role_allows(role: UserRole, scope: ApiScope) -> bool {
+    match scope {
+        ApiScope::PluginsRead => true,
+        ApiScope::PluginsExecute => role != UserRole::Guest,
+        ApiScope::PluginsWrite => role == UserRole::Admin,
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
     pub iat: usize,
+    pub jti: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,17 +82,104 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserInfo,
     pub expires_at: String,
+    pub refresh_expires_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogoutRequest {
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    // Ends every session of the user, not just the one the refresh token belongs to.
+    #[serde(default)]
+    pub all: bool,
 }
 
 #[derive(Debug, Serialize)]
+pub struct RevokeResponse {
+    pub revoked: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoleRequest {
+    pub role: UserRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub name: Option<String>,
+    pub role: Option<UserRole>,
+    // Required with the database provider; other providers check passwords themselves.
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetPasswordRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    // Empty grants everything the owner can do.
+    #[serde(default)]
+    pub scopes: Vec<ApiScope>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateApiKeyResponse {
+    // Only ever returned here; the server keeps just its hash.
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct UserInfo {
     pub id: String,
     pub username: String,
     pub name: String,
-    pub role: String,
+    pub role: UserRole,
     pub is_admin: bool,
+    // Set when authenticated with a scoped API key; `None` allows everything the user can do.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<ApiScope>>,
+    #[serde(skip)]
+    pub api_key_id: Option<Uuid>,
+}
+
+impl UserInfo {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.as_ref().is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    /// Checks the user's role first, then the scopes of the API key used, if any.
+    pub fn authorize(&self, scope: ApiScope) -> Result<(), String> {
+        if !role_allows(self.role, scope) {
+            return Err(format!("The {} role does not allow {}", self.role.as_str(), scope.as_str()));
+        }
+        if !self.has_scope(scope) {
+            return Err(format!("API key is missing the {} scope", scope.as_str()));
+        }
+        Ok(())
+    }
+
+    pub fn user_id(&self) -> Option<Uuid> {
+        Uuid::parse_str(&self.id).ok()
+    }
+
+    /// Whether this user may see or act on a record started by `owner`; admins see everything.
+    pub fn can_access(&self, owner: Option<Uuid>) -> bool {
+        self.is_admin || (owner.is_some() && owner == self.user_id())
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -49,40 +187,168 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidToken(jsonwebtoken::errors::Error),
+    InvalidApiKey,
+    Revoked,
+    UnknownUser,
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidToken(e) => write!(f, "invalid token: {}", e),
+            AuthError::InvalidApiKey => write!(f, "unknown, expired or revoked API key"),
+            AuthError::Revoked => write!(f, "token has been revoked"),
+            AuthError::UnknownUser => write!(f, "user does not exist or is disabled"),
+            AuthError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<jsonwebtoken::errors::Error> for AuthError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        AuthError::InvalidToken(e)
+    }
+}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        AuthError::Database(e)
+    }
+}
+
 pub struct AuthConfig {
     pub jwt_secret: String,
+    pub access_token_lifetime: Duration,
+    pub refresh_token_lifetime: Duration,
+    // Role given to accounts on their first login unless the provider reports them as administrators.
+    pub default_role: UserRole,
+    provider: Arc<dyn AuthProvider + Send + Sync>,
+    tokens: Arc<dyn TokenRepository + Send + Sync>,
+    users: Arc<dyn UserRepository + Send + Sync>,
 }
 
 impl AuthConfig {
-    pub fn new() -> Self {
+    pub fn new(
+        jwt_secret: String,
+        access_token_lifetime: Duration,
+        refresh_token_lifetime: Duration,
+        default_role: UserRole,
+        provider: Arc<dyn AuthProvider + Send + Sync>,
+        tokens: Arc<dyn TokenRepository + Send + Sync>,
+        users: Arc<dyn UserRepository + Send + Sync>,
+    ) -> Self {
         Self {
-            jwt_secret: "your-secret-key-change-in-production".to_string(),
+            jwt_secret,
+            access_token_lifetime,
+            refresh_token_lifetime,
+            default_role,
+            provider,
+            tokens,
+            users,
         }
     }
 }
 
-fn check_user_privileges(username: &str) -> (bool, String) {
-    if username == "root" {
-        return (true, "root".to_string());
+fn auth_error(status: StatusCode, message: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.to_string(),
+        }),
+    )
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn user_info(user: &User) -> UserInfo {
+    UserInfo {
+        id: user.id.to_string(),
+        username: user.username.clone(),
+        name: user.name.clone(),
+        role: user.role,
+        is_admin: user.role == UserRole::Admin,
+        scopes: None,
+        api_key_id: None,
     }
-    
-    let output = std::process::Command::new("sudo")
-        .args(["-l", "-U", username])
-        .output();
-    
-    match output {
-        Ok(output) => {
-            if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
-                if output_str.contains("(ALL : ALL)") || output_str.contains("(root)") {
-                    return (true, "sudo".to_string());
-                }
-            }
+}
+
+// Roles are read on every request so a role change or deactivation applies immediately.
+async fn load_user(config: &AuthConfig, username: &str) -> Result<UserInfo, AuthError> {
+    config.users.get_user_by_username(username).await?
+        .filter(|user| user.is_active)
+        .map(|user| user_info(&user))
+        .ok_or(AuthError::UnknownUser)
+}
+
+// Issues a new access token plus a refresh token in `family_id`; with `rotate`, the given
+// refresh token is replaced atomically and `None` means it had already been used.
+async fn issue_tokens(
+    config: &AuthConfig,
+    user: UserInfo,
+    family_id: Uuid,
+    rotate: Option<Uuid>,
+) -> Result<Option<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let now = Utc::now();
+    let expires_at = now + config.access_token_lifetime;
+    let refresh_expires_at = now + config.refresh_token_lifetime;
+    let jti = Uuid::new_v4();
+
+    let claims = Claims {
+        sub: user.username.clone(),
+        exp: expires_at.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: jti.to_string(),
+    };
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_ref()),
+    )
+    .map_err(|_| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate authentication token"))?;
+
+    // Two v4 UUIDs give 244 random bits; only the hash is stored.
+    let refresh_token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let record = NewRefreshToken {
+        family_id,
+        username: user.username.clone(),
+        token_hash: hash_token(&refresh_token),
+        access_jti: jti,
+        access_expires_at: expires_at,
+        expires_at: refresh_expires_at,
+    };
+
+    let stored = match rotate {
+        Some(old_id) => config.tokens.rotate_refresh_token(old_id, record).await.map(|t| t.is_some()),
+        None => config.tokens.create_refresh_token(record).await.map(|_| true),
+    };
+    match stored {
+        Ok(true) => {}
+        Ok(false) => return Ok(None),
+        Err(e) => {
+            eprintln!("Failed to store refresh token for {}: {}", user.username, e);
+            return Err(auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate authentication token"));
         }
-        Err(_) => {}
     }
-    
-    (false, "user".to_string())
+
+    Ok(Some(LoginResponse {
+        token,
+        refresh_token,
+        user,
+        expires_at: expires_at.to_rfc3339(),
+        refresh_expires_at: refresh_expires_at.to_rfc3339(),
+    }))
 }
 
 pub async fn login(
@@ -90,141 +356,725 @@ pub async fn login(
     Json(payload): Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
     println!("Login attempt for user: {}", payload.username);
-    let mut authenticator = Authenticator::with_password("login")
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "Failed to initialize PAM authentication".to_string(),
-                }),
-            )
+    if payload.username.is_empty() || payload.password.is_empty() {
+        return Err(auth_error(StatusCode::UNAUTHORIZED, "Invalid username or password"));
+    }
+
+    let authenticated = config.provider.authenticate(&payload.username, &payload.password).await
+        .map_err(|e| {
+            eprintln!("{} authentication failed for {}: {}", config.provider.name(), payload.username, e);
+            auth_error(StatusCode::SERVICE_UNAVAILABLE, "Authentication service unavailable")
+        })?
+        .ok_or_else(|| auth_error(StatusCode::UNAUTHORIZED, "Invalid username or password"))?;
+
+    let existing = config.users.get_user_by_username(&authenticated.username).await
+        .map_err(|e| {
+            eprintln!("Failed to look up user {}: {}", authenticated.username, e);
+            auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to log in")
+        })?;
+    let role = match &existing {
+        Some(user) => user.role,
+        None if authenticated.is_admin => UserRole::Admin,
+        None => config.default_role,
+    };
+    let user = config.users
+        .upsert_user_on_login(&authenticated.username, &authenticated.name, role)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to record login of {}: {}", payload.username, e);
+            auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to log in")
         })?;
+    if !user.is_active {
+        return Err(auth_error(StatusCode::FORBIDDEN, "Account is disabled"));
+    }
 
-    authenticator
-        .get_handler()
-        .set_credentials(&payload.username, &payload.password);
-
-    match authenticator.authenticate() {
-        Ok(_) => {
-            let (is_admin, role) = check_user_privileges(&payload.username);
-            
-            let real_name = std::process::Command::new("getent")
-                .args(["passwd", &payload.username])
-                .output()
-                .ok()
-                .and_then(|output| {
-                    if output.status.success() {
-                        let line = String::from_utf8_lossy(&output.stdout);
-                        line.split(':').nth(4).map(|s| s.trim().to_string())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_else(|| payload.username.clone());
-
-            let now = Utc::now();
-            let expires_at = now + Duration::hours(24);
-            
-            let claims = Claims {
-                sub: payload.username.clone(),
-                exp: expires_at.timestamp() as usize,
-                iat: now.timestamp() as usize,
-            };
+    let response = issue_tokens(&config, user_info(&user), Uuid::new_v4(), None)
+        .await?
+        .ok_or_else(|| auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate authentication token"))?;
 
-            let token = encode(
-                &Header::default(),
-                &claims,
-                &EncodingKey::from_secret(config.jwt_secret.as_ref()),
-            )
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "Failed to generate authentication token".to_string(),
-                    }),
-                )
-            })?;
-
-            let user_info = UserInfo {
-                id: payload.username.clone(),
-                username: payload.username.clone(),
-                name: real_name,
-                role,
-                is_admin,
-            };
+    println!("Login successful for user: {}", payload.username);
+    Ok(Json(response))
+}
 
-            println!("Login successful for user: {}", payload.username);
-            Ok(Json(LoginResponse {
-                token,
-                user: user_info,
-                expires_at: expires_at.to_rfc3339(),
-            }))
-        }
-        Err(_) => Err((
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse {
-                error: "Invalid username or password".to_string(),
-            }),
-        )),
+pub async fn refresh(
+    State(config): State<Arc<AuthConfig>>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let invalid = || auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired refresh token");
+
+    let stored = config.tokens.get_refresh_token(&hash_token(&payload.refresh_token)).await
+        .map_err(|e| {
+            eprintln!("Failed to look up refresh token: {}", e);
+            auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to refresh token")
+        })?
+        .ok_or_else(invalid)?;
+
+    if stored.expires_at <= Utc::now() {
+        return Err(invalid());
+    }
+
+    // A rotated token coming back means it was copied; end the whole session to be safe.
+    if stored.revoked_at.is_none() {
+        let user = match load_user(&config, &stored.username).await {
+            Ok(user) => user,
+            Err(AuthError::UnknownUser) => {
+                let _ = config.tokens.revoke_refresh_family(stored.family_id).await;
+                return Err(invalid());
+            }
+            Err(e) => {
+                eprintln!("Failed to load user {}: {}", stored.username, e);
+                return Err(auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to refresh token"));
+            }
+        };
+        if let Some(response) = issue_tokens(&config, user, stored.family_id, Some(stored.id)).await? {
+            return Ok(Json(response));
+        }
+    }
+
+    eprintln!("Refresh token reuse detected for {}, revoking session {}", stored.username, stored.family_id);
+    if let Err(e) = config.tokens.revoke_refresh_family(stored.family_id).await {
+        eprintln!("Failed to revoke session {}: {}", stored.family_id, e);
     }
+    Err(invalid())
 }
 
-pub async fn validate_token(
+pub async fn logout(
     State(config): State<Arc<AuthConfig>>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-) -> Result<Json<UserInfo>, (StatusCode, Json<ErrorResponse>)> {
-    let token = bearer.token();
-    
-    println!("Validating token: {}", token);
-    
-    let token_data = decode::<Claims>(
+    payload: Option<Json<LogoutRequest>>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let claims = authenticate_claims(&config, bearer.token()).await
+        .map_err(|_| auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+    let failed = |e: sqlx::Error| {
+        eprintln!("Failed to log out {}: {}", claims.sub, e);
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to log out")
+    };
+
+    if payload.all {
+        config.tokens.revoke_user_tokens(&claims.sub).await.map_err(failed)?;
+    } else if let Some(refresh_token) = payload.refresh_token {
+        let stored = config.tokens.get_refresh_token(&hash_token(&refresh_token)).await.map_err(failed)?;
+        if let Some(stored) = stored.filter(|stored| stored.username == claims.sub) {
+            config.tokens.revoke_refresh_family(stored.family_id).await.map_err(failed)?;
+        }
+    }
+
+    // The presented access token is revoked in every case.
+    if let Ok(jti) = Uuid::parse_str(&claims.jti) {
+        let expires_at = Utc.timestamp_opt(claims.exp as i64, 0).single().unwrap_or_else(Utc::now);
+        config.tokens.revoke_access_token(jti, &claims.sub, expires_at).await.map_err(failed)?;
+    }
+
+    println!("User {} logged out", claims.sub);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// User administration needs an admin's login session or unscoped API key.
+async fn authenticate_admin(config: &AuthConfig, token: &str) -> Result<UserInfo, (StatusCode, Json<ErrorResponse>)> {
+    let admin = authenticate_token(config, token).await
+        .map_err(|_| auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+    if !admin.is_admin || admin.scopes.is_some() {
+        return Err(auth_error(StatusCode::FORBIDDEN, "Only administrators can manage users"));
+    }
+    Ok(admin)
+}
+
+pub async fn revoke_user_sessions(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(username): Path<String>,
+) -> Result<Json<RevokeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let admin = authenticate_admin(&config, bearer.token()).await?;
+
+    let failed = |e: sqlx::Error| {
+        eprintln!("Failed to revoke sessions of {}: {}", username, e);
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke sessions")
+    };
+    // Locking out a user, e.g. after a lost laptop, also takes away the keys they created.
+    let sessions = config.tokens.revoke_user_tokens(&username).await.map_err(failed)?;
+    let keys = config.tokens.revoke_user_api_keys(&username).await.map_err(failed)?;
+
+    println!("{} revoked {} sessions and {} API keys of {}", admin.username, sessions, keys, username);
+    Ok(Json(RevokeResponse { revoked: sessions + keys }))
+}
+
+pub async fn list_users(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<Vec<User>>, (StatusCode, Json<ErrorResponse>)> {
+    authenticate_admin(&config, bearer.token()).await?;
+
+    let users = config.users.list_users().await
+        .map_err(|e| {
+            eprintln!("Failed to list users: {}", e);
+            auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list users")
+        })?;
+
+    Ok(Json(users))
+}
+
+pub async fn set_user_role(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(username): Path<String>,
+    Json(payload): Json<SetRoleRequest>,
+) -> Result<Json<User>, (StatusCode, Json<ErrorResponse>)> {
+    let admin = authenticate_admin(&config, bearer.token()).await?;
+    let failed = |e: sqlx::Error| {
+        eprintln!("Failed to change role of {}: {}", username, e);
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change role")
+    };
+
+    let user = config.users.get_user_by_username(&username).await
+        .map_err(failed)?
+        .ok_or_else(|| auth_error(StatusCode::NOT_FOUND, "User not found"))?;
+
+    // Demoting the last admin would leave nobody able to manage plugins or roles.
+    if user.role == UserRole::Admin && payload.role != UserRole::Admin && user.is_active {
+        let admins = config.users.count_active_admins().await.map_err(failed)?;
+        if admins <= 1 {
+            return Err(auth_error(StatusCode::CONFLICT, "Cannot demote the last administrator"));
+        }
+    }
+
+    let user = config.users.set_user_role(&username, payload.role).await
+        .map_err(failed)?
+        .ok_or_else(|| auth_error(StatusCode::NOT_FOUND, "User not found"))?;
+
+    println!("{} changed role of {} to {}", admin.username, username, user.role.as_str());
+    Ok(Json(user))
+}
+
+pub async fn create_user(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<CreateUserRequest>,
+) -> Result<(StatusCode, Json<User>), (StatusCode, Json<ErrorResponse>)> {
+    let admin = authenticate_admin(&config, bearer.token()).await?;
+
+    let username = payload.username.trim();
+    if username.is_empty() || username.len() > 255 {
+        return Err(auth_error(StatusCode::BAD_REQUEST, "Username must be between 1 and 255 characters"));
+    }
+    let password_hash = match payload.password {
+        Some(password) if config.provider.manages_passwords() => Some(new_password_hash(&password)?),
+        Some(_) => {
+            return Err(auth_error(
+                StatusCode::BAD_REQUEST,
+                "Passwords are managed by the authentication provider",
+            ));
+        }
+        None if config.provider.manages_passwords() => {
+            return Err(auth_error(StatusCode::BAD_REQUEST, "A password is required"));
+        }
+        None => None,
+    };
+    let name = payload.name.as_deref().map(str::trim).filter(|name| !name.is_empty()).unwrap_or(username);
+    let role = payload.role.unwrap_or(config.default_role);
+
+    let user = config.users.create_user(username, name, role, password_hash.as_deref()).await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                auth_error(StatusCode::CONFLICT, "User already exists")
+            }
+            e => {
+                eprintln!("Failed to create user {}: {}", username, e);
+                auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create user")
+            }
+        })?;
+
+    println!("{} created user {} with role {}", admin.username, user.username, user.role.as_str());
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn set_user_password(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(username): Path<String>,
+    Json(payload): Json<SetPasswordRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let admin = authenticate_admin(&config, bearer.token()).await?;
+    if !config.provider.manages_passwords() {
+        return Err(auth_error(StatusCode::BAD_REQUEST, "Passwords are managed by the authentication provider"));
+    }
+
+    let password_hash = new_password_hash(&payload.password)?;
+    match config.users.set_password_hash(&username, &password_hash).await {
+        Ok(true) => {}
+        Ok(false) => return Err(auth_error(StatusCode::NOT_FOUND, "User not found")),
+        Err(e) => {
+            eprintln!("Failed to set password of {}: {}", username, e);
+            return Err(auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to set password"));
+        }
+    }
+
+    // Sessions opened with the old password should not outlive it.
+    if let Err(e) = config.tokens.revoke_user_tokens(&username).await {
+        eprintln!("Failed to revoke sessions of {}: {}", username, e);
+    }
+
+    println!("{} set the password of {}", admin.username, username);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn new_password_hash(password: &str) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(auth_error(
+            StatusCode::BAD_REQUEST,
+            &format!("Password must be at least {} characters long", MIN_PASSWORD_LEN),
+        ));
+    }
+    hash_password(password).map_err(|e| {
+        eprintln!("Failed to hash password: {}", e);
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to set password")
+    })
+}
+
+pub fn decode_token(config: &AuthConfig, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    decode::<Claims>(
         token,
         &DecodingKey::from_secret(config.jwt_secret.as_ref()),
         &Validation::default(),
     )
+    .map(|token_data| token_data.claims)
+}
+
+pub async fn create_api_key(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let user = authenticate_token(&config, bearer.token()).await
+        .map_err(|_| auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+    // Otherwise a leaked narrow key could mint itself a broader one.
+    if user.api_key_id.is_some() {
+        return Err(auth_error(StatusCode::FORBIDDEN, "API keys can only be created from a login session"));
+    }
+
+    let name = payload.name.trim();
+    if name.is_empty() || name.len() > 255 {
+        return Err(auth_error(StatusCode::BAD_REQUEST, "API key name must be between 1 and 255 characters"));
+    }
+    let expires_at = match payload.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(auth_error(StatusCode::BAD_REQUEST, "expires_in_days must be at least 1"));
+        }
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+
+    let mut scopes: Vec<String> = payload.scopes.iter().map(|scope| scope.as_str().to_string()).collect();
+    scopes.sort();
+    scopes.dedup();
+
+    let key = format!("{}{}{}", API_KEY_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let api_key = config.tokens.create_api_key(NewApiKey {
+        username: user.username.clone(),
+        name: name.to_string(),
+        key_prefix: key[..API_KEY_DISPLAY_LEN].to_string(),
+        key_hash: hash_token(&key),
+        scopes,
+        expires_at,
+    }).await
     .map_err(|e| {
-        println!("Token validation error: {:?}", e);
-        (
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse {
-                error: "Invalid or expired token".to_string(),
-            }),
-        )
+        eprintln!("Failed to create API key for {}: {}", user.username, e);
+        auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create API key")
     })?;
 
-    let username = token_data.claims.sub;
-    println!("Token validated for user: {}", username);
-    
-    let (is_admin, role) = check_user_privileges(&username);
-    
-    let real_name = std::process::Command::new("getent")
-        .args(["passwd", &username])
-        .output()
-        .ok()
-        .and_then(|output| {
-            if output.status.success() {
-                let line = String::from_utf8_lossy(&output.stdout);
-                line.split(':').nth(4).map(|s| s.trim().to_string())
-            } else {
-                None
+    println!("User {} created API key {}", user.username, api_key.id);
+    Ok((StatusCode::CREATED, Json(CreateApiKeyResponse { key, api_key })))
+}
+
+pub async fn list_api_keys(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<Vec<ApiKey>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = authenticate_token(&config, bearer.token()).await
+        .map_err(|_| auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+
+    let keys = config.tokens.list_api_keys(&user.username).await
+        .map_err(|e| {
+            eprintln!("Failed to list API keys of {}: {}", user.username, e);
+            auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list API keys")
+        })?;
+
+    Ok(Json(keys))
+}
+
+pub async fn revoke_api_key(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(key_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = authenticate_token(&config, bearer.token()).await
+        .map_err(|_| auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+    let not_found = || auth_error(StatusCode::NOT_FOUND, "API key not found");
+    let key_id = Uuid::parse_str(&key_id).map_err(|_| not_found())?;
+
+    match config.tokens.revoke_api_key(key_id, &user.username).await {
+        Ok(true) => {
+            println!("User {} revoked API key {}", user.username, key_id);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err(not_found()),
+        Err(e) => {
+            eprintln!("Failed to revoke API key {}: {}", key_id, e);
+            Err(auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke API key"))
+        }
+    }
+}
+
+async fn authenticate_claims(config: &AuthConfig, token: &str) -> Result<Claims, AuthError> {
+    let claims = decode_token(config, token)?;
+    let jti = Uuid::parse_str(&claims.jti)
+        .map_err(|_| AuthError::InvalidToken(ErrorKind::InvalidToken.into()))?;
+
+    if config.tokens.is_access_token_revoked(jti).await? {
+        return Err(AuthError::Revoked);
+    }
+    Ok(claims)
+}
+
+async fn authenticate_api_key(config: &AuthConfig, key: &str) -> Result<UserInfo, AuthError> {
+    let api_key = config.tokens.get_api_key_by_hash(&hash_token(key)).await?
+        .filter(ApiKey::is_active)
+        .ok_or(AuthError::InvalidApiKey)?;
+
+    // Keys stop working with their owner's account, even before they are revoked.
+    let mut user = load_user(config, &api_key.username).await?;
+
+    if let Err(e) = config.tokens.touch_api_key(api_key.id).await {
+        eprintln!("Failed to record use of API key {}: {}", api_key.id, e);
+    }
+
+    user.api_key_id = Some(api_key.id);
+    if !api_key.scopes.is_empty() {
+        user.scopes = Some(api_key.scopes.iter().filter_map(|scope| ApiScope::parse(scope)).collect());
+    }
+    Ok(user)
+}
+
+/// Accepts either a JWT access token or an API key.
+pub async fn authenticate_token(config: &AuthConfig, token: &str) -> Result<UserInfo, AuthError> {
+    if token.starts_with(API_KEY_PREFIX) {
+        return authenticate_api_key(config, token).await;
+    }
+
+    let claims = authenticate_claims(config, token).await?;
+    load_user(config, &claims.sub).await
+}
+
+pub async fn validate_token(
+    State(config): State<Arc<AuthConfig>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<UserInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let user_info = authenticate_token(&config, bearer.token()).await
+        .map_err(|e| {
+            println!("Token validation error: {}", e);
+            match e {
+                AuthError::Database(_) => auth_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to validate token"),
+                _ => auth_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"),
             }
-        })
-        .unwrap_or_else(|| username.clone());
-
-    let user_info = UserInfo {
-        id: username.clone(),
-        username,
-        name: real_name,
-        role,
-        is_admin,
-    };
+        })?;
 
-    println!("Returning user info: {:?}", user_info);
+    println!("Token validated for user: {}", user_info.username);
     Ok(Json(user_info))
 }
 
+// Revoked access tokens only need to be remembered until they would have expired anyway.
+pub fn start_token_cleanup(config: Arc<AuthConfig>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            match config.tokens.delete_expired_tokens().await {
+                Ok(0) => {}
+                Ok(count) => println!("Deleted {} expired tokens", count),
+                Err(e) => eprintln!("Failed to delete expired tokens: {}", e),
+            }
+        }
+    });
+}
+
 pub fn auth_routes() -> Router<Arc<AuthConfig>> {
     Router::new()
         .route("/login", post(login))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
         .route("/validate", get(validate_token))
-}
\ No newline at end of file
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/:username/role", put(set_user_role))
+        .route("/users/:username/password", put(set_user_password))
+        .route("/users/:username/revoke", post(revoke_user_sessions))
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/:id", delete(revoke_api_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_provider::AuthProviderConfig;
+    use crate::database::RefreshToken;
+    use chrono::DateTime;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+
+    // Follows the rotation and revocation rules of the Postgres repository; API keys are not used here.
+    #[derive(Default)]
+    struct MockTokens {
+        refresh_tokens: Mutex<Vec<RefreshToken>>,
+        revoked_jtis: Mutex<HashSet<Uuid>>,
+    }
+
+    impl MockTokens {
+        fn insert(&self, id: Uuid, token: NewRefreshToken) -> RefreshToken {
+            let token = RefreshToken {
+                id,
+                family_id: token.family_id,
+                username: token.username,
+                token_hash: token.token_hash,
+                access_jti: token.access_jti,
+                access_expires_at: token.access_expires_at,
+                created_at: Utc::now(),
+                expires_at: token.expires_at,
+                revoked_at: None,
+                replaced_by: None,
+            };
+            self.refresh_tokens.lock().unwrap().push(token.clone());
+            token
+        }
+
+        fn revoke_where(&self, matches: impl Fn(&RefreshToken) -> bool) -> u64 {
+            let mut revoked = 0;
+            for token in self.refresh_tokens.lock().unwrap().iter_mut().filter(|token| matches(token)) {
+                self.revoked_jtis.lock().unwrap().insert(token.access_jti);
+                if token.revoked_at.is_none() {
+                    token.revoked_at = Some(Utc::now());
+                    revoked += 1;
+                }
+            }
+            revoked
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl TokenRepository for MockTokens {
+        async fn create_refresh_token(&self, token: NewRefreshToken) -> Result<RefreshToken, sqlx::Error> {
+            Ok(self.insert(Uuid::new_v4(), token))
+        }
+
+        async fn get_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, sqlx::Error> {
+            Ok(self.refresh_tokens.lock().unwrap().iter().find(|token| token.token_hash == token_hash).cloned())
+        }
+
+        async fn rotate_refresh_token(&self, old_id: Uuid, next: NewRefreshToken) -> Result<Option<RefreshToken>, sqlx::Error> {
+            let mut tokens = self.refresh_tokens.lock().unwrap();
+            let Some(old) = tokens.iter_mut().find(|token| token.id == old_id && token.revoked_at.is_none()) else {
+                return Ok(None);
+            };
+            let next_id = Uuid::new_v4();
+            old.revoked_at = Some(Utc::now());
+            old.replaced_by = Some(next_id);
+            drop(tokens);
+            Ok(Some(self.insert(next_id, next)))
+        }
+
+        async fn revoke_refresh_family(&self, family_id: Uuid) -> Result<u64, sqlx::Error> {
+            Ok(self.revoke_where(|token| token.family_id == family_id))
+        }
+
+        async fn revoke_user_tokens(&self, username: &str) -> Result<u64, sqlx::Error> {
+            Ok(self.revoke_where(|token| token.username == username))
+        }
+
+        async fn revoke_user_api_keys(&self, _: &str) -> Result<u64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn revoke_access_token(&self, jti: Uuid, _: &str, _: DateTime<Utc>) -> Result<(), sqlx::Error> {
+            self.revoked_jtis.lock().unwrap().insert(jti);
+            Ok(())
+        }
+
+        async fn is_access_token_revoked(&self, jti: Uuid) -> Result<bool, sqlx::Error> {
+            Ok(self.revoked_jtis.lock().unwrap().contains(&jti))
+        }
+
+        async fn delete_expired_tokens(&self) -> Result<u64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn create_api_key(&self, _: NewApiKey) -> Result<ApiKey, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn list_api_keys(&self, _: &str) -> Result<Vec<ApiKey>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn get_api_key_by_hash(&self, _: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn revoke_api_key(&self, _: Uuid, _: &str) -> Result<bool, sqlx::Error> {
+            Ok(false)
+        }
+
+        async fn touch_api_key(&self, _: Uuid) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+    }
+
+    // Only the lookups that token checks make return data.
+    #[derive(Default)]
+    struct MockUsers {
+        users: Mutex<HashMap<String, User>>,
+    }
+
+    #[async_trait::async_trait]
+    impl UserRepository for MockUsers {
+        async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, sqlx::Error> {
+            Ok(self.users.lock().unwrap().get(username).cloned())
+        }
+
+        async fn upsert_user_on_login(&self, _: &str, _: &str, _: UserRole) -> Result<User, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn list_users(&self) -> Result<Vec<User>, sqlx::Error> {
+            Ok(self.users.lock().unwrap().values().cloned().collect())
+        }
+
+        async fn set_user_role(&self, _: &str, _: UserRole) -> Result<Option<User>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn count_active_admins(&self) -> Result<i64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn create_user(&self, _: &str, _: &str, _: UserRole, _: Option<&str>) -> Result<User, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn get_password_hash(&self, _: &str) -> Result<Option<String>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn set_password_hash(&self, _: &str, _: &str) -> Result<bool, sqlx::Error> {
+            Ok(false)
+        }
+    }
+
+    struct Fixture {
+        config: Arc<AuthConfig>,
+        tokens: Arc<MockTokens>,
+        users: Arc<MockUsers>,
+    }
+
+    fn fixture() -> Fixture {
+        let tokens = Arc::new(MockTokens::default());
+        let users = Arc::new(MockUsers::default());
+        let alice = User {
+            id: Uuid::new_v4(),
+            username: "alice".to_string(),
+            email: None,
+            name: "Alice".to_string(),
+            role: UserRole::User,
+            is_active: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            last_login_at: None,
+        };
+        users.users.lock().unwrap().insert(alice.username.clone(), alice);
+
+        let config = Arc::new(AuthConfig::new(
+            "a-test-secret-that-is-long-enough-to-sign".to_string(),
+            Duration::minutes(15),
+            Duration::days(30),
+            UserRole::User,
+            AuthProviderConfig::Database.build(users.clone()),
+            tokens.clone(),
+            users.clone(),
+        ));
+        Fixture { config, tokens, users }
+    }
+
+    async fn log_in(fixture: &Fixture) -> LoginResponse {
+        let user = load_user(&fixture.config, "alice").await.unwrap();
+        issue_tokens(&fixture.config, user, Uuid::new_v4(), None).await.unwrap().unwrap()
+    }
+
+    async fn refresh_with(fixture: &Fixture, refresh_token: &str) -> Result<LoginResponse, StatusCode> {
+        refresh(
+            State(fixture.config.clone()),
+            Json(RefreshRequest {
+                refresh_token: refresh_token.to_string(),
+            }),
+        )
+        .await
+        .map(|Json(response)| response)
+        .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn refreshing_rotates_the_refresh_token() {
+        let fixture = fixture();
+        let first = log_in(&fixture).await;
+
+        let second = refresh_with(&fixture, &first.refresh_token).await.unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert_eq!(second.user.username, "alice");
+        assert!(authenticate_token(&fixture.config, &second.token).await.is_ok());
+
+        let old = fixture.tokens.get_refresh_token(&hash_token(&first.refresh_token)).await.unwrap().unwrap();
+        let new = fixture.tokens.get_refresh_token(&hash_token(&second.refresh_token)).await.unwrap().unwrap();
+        assert!(old.revoked_at.is_some());
+        assert_eq!(old.replaced_by, Some(new.id));
+        assert_eq!(old.family_id, new.family_id);
+    }
+
+    #[tokio::test]
+    async fn reusing_a_rotated_refresh_token_revokes_the_whole_session() {
+        let fixture = fixture();
+        let first = log_in(&fixture).await;
+        let second = refresh_with(&fixture, &first.refresh_token).await.unwrap();
+
+        assert_eq!(refresh_with(&fixture, &first.refresh_token).await.err(), Some(StatusCode::UNAUTHORIZED));
+
+        // The legitimate holder of the latest tokens is logged out as well.
+        assert_eq!(refresh_with(&fixture, &second.refresh_token).await.err(), Some(StatusCode::UNAUTHORIZED));
+        assert!(matches!(authenticate_token(&fixture.config, &second.token).await, Err(AuthError::Revoked)));
+    }
+
+    #[tokio::test]
+    async fn reuse_only_ends_the_session_it_belongs_to() {
+        let fixture = fixture();
+        let laptop = log_in(&fixture).await;
+        let phone = log_in(&fixture).await;
+        refresh_with(&fixture, &laptop.refresh_token).await.unwrap();
+
+        assert!(refresh_with(&fixture, &laptop.refresh_token).await.is_err());
+        assert!(authenticate_token(&fixture.config, &phone.token).await.is_ok());
+        assert!(refresh_with(&fixture, &phone.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refreshing_for_a_disabled_user_fails_and_ends_the_session() {
+        let fixture = fixture();
+        let first = log_in(&fixture).await;
+        fixture.users.users.lock().unwrap().get_mut("alice").unwrap().is_active = false;
+
+        assert_eq!(refresh_with(&fixture, &first.refresh_token).await.err(), Some(StatusCode::UNAUTHORIZED));
+        let stored = fixture.tokens.get_refresh_token(&hash_token(&first.refresh_token)).await.unwrap().unwrap();
+        assert!(stored.revoked_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn unknown_refresh_tokens_are_rejected() {
+        let fixture = fixture();
+
+        assert_eq!(refresh_with(&fixture, "not-a-token").await.err(), Some(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,557 @@
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use ldap3::{LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::database::UserRepository;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+// LDAP result code for a failed simple bind.
+const LDAP_INVALID_CREDENTIALS: u32 = 49;
+
+/// An account whose password was accepted by a provider.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub username: String,
+    pub name: String,
+    // Decides the role on first login only; the stored role wins afterwards.
+    pub is_admin: bool,
+}
+
+#[derive(Debug)]
+pub enum ProviderError {
+    Unavailable(String),
+    Database(sqlx::Error),
+    Ldap(ldap3::LdapError),
+    Hash(argon2::password_hash::Error),
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Unavailable(message) => write!(f, "authentication backend unavailable: {}", message),
+            ProviderError::Database(e) => write!(f, "database error: {}", e),
+            ProviderError::Ldap(e) => write!(f, "LDAP error: {}", e),
+            ProviderError::Hash(e) => write!(f, "password hash error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<sqlx::Error> for ProviderError {
+    fn from(e: sqlx::Error) -> Self {
+        ProviderError::Database(e)
+    }
+}
+
+impl From<ldap3::LdapError> for ProviderError {
+    fn from(e: ldap3::LdapError) -> Self {
+        ProviderError::Ldap(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for ProviderError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        ProviderError::Hash(e)
+    }
+}
+
+/// Checks a username and password; `Ok(None)` means the credentials were rejected.
+#[async_trait::async_trait]
+pub trait AuthProvider {
+    fn name(&self) -> &'static str;
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>, ProviderError>;
+    // Only the database provider keeps passwords that Sandcrate itself can change.
+    fn manages_passwords(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    pub url: String,
+    // DN to bind as, with `{username}` replaced by the escaped login name.
+    pub user_dn: String,
+    // Members of this group (via `memberOf`) start as admins.
+    pub admin_group: Option<String>,
+    pub name_attribute: String,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum AuthProviderConfig {
+    Pam { service: String },
+    Database,
+    Ldap(LdapConfig),
+}
+
+impl AuthProviderConfig {
+    pub fn build(&self, users: Arc<dyn UserRepository + Send + Sync>) -> Arc<dyn AuthProvider + Send + Sync> {
+        match self {
+            #[cfg(feature = "pam")]
+            AuthProviderConfig::Pam { service } => Arc::new(PamProvider { service: service.clone() }),
+            #[cfg(not(feature = "pam"))]
+            AuthProviderConfig::Pam { .. } => unreachable!("rejected when the configuration is loaded"),
+            AuthProviderConfig::Database => Arc::new(DatabaseProvider { users }),
+            AuthProviderConfig::Ldap(config) => Arc::new(LdapProvider { config: config.clone() }),
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, ProviderError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> Result<bool, ProviderError> {
+    let hash = PasswordHash::new(hash)?;
+    match Argon2::default().verify_password(password.as_bytes(), &hash) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(feature = "pam")]
+pub struct PamProvider {
+    service: String,
+}
+
+#[cfg(feature = "pam")]
+#[async_trait::async_trait]
+impl AuthProvider for PamProvider {
+    fn name(&self) -> &'static str {
+        "pam"
+    }
+
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>, ProviderError> {
+        let service = self.service.clone();
+        let username = username.to_string();
+        let password = password.to_string();
+
+        // PAM modules may sleep after a failure, so keep them off the async workers.
+        tokio::task::spawn_blocking(move || {
+            let mut authenticator = pam::Authenticator::with_password(&service)
+                .map_err(|_| ProviderError::Unavailable(format!("failed to initialize PAM service {}", service)))?;
+            authenticator.get_handler().set_credentials(&username, &password);
+
+            if authenticator.authenticate().is_err() {
+                return Ok(None);
+            }
+            Ok(Some(AuthenticatedUser {
+                name: lookup_real_name(&username),
+                // Sudo rights are not a Sandcrate role; other admins are promoted with `sandcrate-user`.
+                is_admin: username == "root",
+                username,
+            }))
+        })
+        .await
+        .map_err(|e| ProviderError::Unavailable(format!("PAM task failed: {}", e)))?
+    }
+}
+
+#[cfg(feature = "pam")]
+fn lookup_real_name(username: &str) -> String {
+    std::process::Command::new("getent")
+        .args(["passwd", username])
+        .output()
+        .ok()
+        .and_then(|output| {
+            if output.status.success() {
+                let line = String::from_utf8_lossy(&output.stdout);
+                line.split(':').nth(4).map(|s| s.trim().to_string())
+            } else {
+                None
+            }
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| username.to_string())
+}
+
+/// Passwords stored as argon2 hashes in the `users` table.
+pub struct DatabaseProvider {
+    users: Arc<dyn UserRepository + Send + Sync>,
+}
+
+#[async_trait::async_trait]
+impl AuthProvider for DatabaseProvider {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>, ProviderError> {
+        static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+        let user = self.users.get_user_by_username(username).await?;
+        let stored = match &user {
+            Some(user) if user.is_active => self.users.get_password_hash(username).await?,
+            _ => None,
+        };
+
+        // Unknown accounts still pay for one hash so response times do not reveal which names exist.
+        let hash = match &stored {
+            Some(hash) => hash.clone(),
+            None => DUMMY_HASH.get_or_init(|| hash_password("sandcrate").unwrap_or_default()).clone(),
+        };
+        let password = password.to_string();
+        let valid = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .map_err(|e| ProviderError::Unavailable(format!("password check failed: {}", e)))??;
+
+        match (user, stored) {
+            (Some(user), Some(_)) if valid => Ok(Some(AuthenticatedUser {
+                username: user.username,
+                name: user.name,
+                is_admin: false,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    fn manages_passwords(&self) -> bool {
+        true
+    }
+}
+
+// Escaping keeps a login name like `x,ou=admins` from binding as a different entry.
+fn bind_dn(template: &str, username: &str) -> String {
+    template.replace("{username}", &ldap3::dn_escape(username))
+}
+
+/// Authenticates with a simple bind as the user's own DN.
+pub struct LdapProvider {
+    config: LdapConfig,
+}
+
+#[async_trait::async_trait]
+impl AuthProvider for LdapProvider {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<AuthenticatedUser>, ProviderError> {
+        // An empty password is an unauthenticated bind, which most servers accept.
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let settings = LdapConnSettings::new().set_conn_timeout(self.config.timeout);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        ldap3::drive!(conn);
+
+        let user = ldap_login(&self.config, &mut ldap, username, password).await;
+        let _ = ldap.unbind().await;
+        user
+    }
+}
+
+/// The directory operations a login needs.
+#[async_trait::async_trait]
+trait LdapDirectory {
+    // `Ok(false)` when the server rejects the credentials.
+    async fn bind(&mut self, dn: &str, password: &str) -> Result<bool, ProviderError>;
+    async fn read_entry(&mut self, dn: &str, attrs: Vec<&str>) -> Result<Option<SearchEntry>, ProviderError>;
+}
+
+#[async_trait::async_trait]
+impl LdapDirectory for ldap3::Ldap {
+    async fn bind(&mut self, dn: &str, password: &str) -> Result<bool, ProviderError> {
+        let bind = self.simple_bind(dn, password).await?;
+        if bind.rc == LDAP_INVALID_CREDENTIALS {
+            return Ok(false);
+        }
+        bind.success()?;
+        Ok(true)
+    }
+
+    async fn read_entry(&mut self, dn: &str, attrs: Vec<&str>) -> Result<Option<SearchEntry>, ProviderError> {
+        let (entries, _) = self.search(dn, Scope::Base, "(objectClass=*)", attrs).await?.success()?;
+        Ok(entries.into_iter().next().map(SearchEntry::construct))
+    }
+}
+
+async fn ldap_login<D: LdapDirectory + Send>(
+    config: &LdapConfig,
+    directory: &mut D,
+    username: &str,
+    password: &str,
+) -> Result<Option<AuthenticatedUser>, ProviderError> {
+    let dn = bind_dn(&config.user_dn, username);
+    if !directory.bind(&dn, password).await? {
+        return Ok(None);
+    }
+    let entry = directory
+        .read_entry(&dn, vec![config.name_attribute.as_str(), "memberOf"])
+        .await?;
+
+    let name = entry
+        .as_ref()
+        .and_then(|entry| entry.attrs.get(&config.name_attribute))
+        .and_then(|values| values.first().cloned())
+        .unwrap_or_else(|| username.to_string());
+    let is_admin = match (&config.admin_group, &entry) {
+        (Some(group), Some(entry)) => entry
+            .attrs
+            .get("memberOf")
+            .is_some_and(|groups| groups.iter().any(|g| g.eq_ignore_ascii_case(group))),
+        _ => false,
+    };
+
+    Ok(Some(AuthenticatedUser {
+        username: username.to_string(),
+        name,
+        is_admin,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{User, UserRole};
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    const TEMPLATE: &str = "uid={username},ou=people,dc=example,dc=org";
+
+    // Only the lookups the database provider makes return data.
+    #[derive(Default)]
+    struct MockUsers {
+        users: HashMap<String, (User, Option<String>)>,
+    }
+
+    impl MockUsers {
+        fn with_user(mut self, username: &str, is_active: bool, password: Option<&str>) -> Self {
+            let user = User {
+                id: Uuid::new_v4(),
+                username: username.to_string(),
+                email: None,
+                name: format!("{} (test)", username),
+                role: UserRole::User,
+                is_active,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                last_login_at: None,
+            };
+            let hash = password.map(|password| hash_password(password).unwrap());
+            self.users.insert(username.to_string(), (user, hash));
+            self
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl UserRepository for MockUsers {
+        async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, sqlx::Error> {
+            Ok(self.users.get(username).map(|(user, _)| user.clone()))
+        }
+
+        async fn upsert_user_on_login(&self, _: &str, _: &str, _: UserRole) -> Result<User, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn list_users(&self) -> Result<Vec<User>, sqlx::Error> {
+            Ok(self.users.values().map(|(user, _)| user.clone()).collect())
+        }
+
+        async fn set_user_role(&self, _: &str, _: UserRole) -> Result<Option<User>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn count_active_admins(&self) -> Result<i64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn create_user(&self, _: &str, _: &str, _: UserRole, _: Option<&str>) -> Result<User, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn get_password_hash(&self, username: &str) -> Result<Option<String>, sqlx::Error> {
+            Ok(self.users.get(username).and_then(|(_, hash)| hash.clone()))
+        }
+
+        async fn set_password_hash(&self, _: &str, _: &str) -> Result<bool, sqlx::Error> {
+            Ok(false)
+        }
+    }
+
+    fn database_provider(users: MockUsers) -> DatabaseProvider {
+        DatabaseProvider { users: Arc::new(users) }
+    }
+
+    #[tokio::test]
+    async fn database_provider_accepts_the_right_password() {
+        let provider = database_provider(MockUsers::default().with_user("alice", true, Some("correct horse")));
+
+        let user = provider.authenticate("alice", "correct horse").await.unwrap().unwrap();
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.name, "alice (test)");
+        assert!(!user.is_admin);
+    }
+
+    #[tokio::test]
+    async fn database_provider_rejects_a_wrong_password() {
+        let provider = database_provider(MockUsers::default().with_user("alice", true, Some("correct horse")));
+
+        assert!(provider.authenticate("alice", "battery staple").await.unwrap().is_none());
+        assert!(provider.authenticate("alice", "").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn database_provider_rejects_inactive_users() {
+        let provider = database_provider(MockUsers::default().with_user("bob", false, Some("correct horse")));
+
+        assert!(provider.authenticate("bob", "correct horse").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn database_provider_rejects_unknown_users_and_users_without_a_password() {
+        let provider = database_provider(MockUsers::default().with_user("carol", true, None));
+
+        assert!(provider.authenticate("mallory", "correct horse").await.unwrap().is_none());
+        assert!(provider.authenticate("carol", "").await.unwrap().is_none());
+        assert!(provider.authenticate("carol", "sandcrate").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn bind_dn_substitutes_the_username() {
+        assert_eq!(bind_dn(TEMPLATE, "alice"), "uid=alice,ou=people,dc=example,dc=org");
+    }
+
+    #[test]
+    fn bind_dn_escapes_special_characters() {
+        let dn = bind_dn(TEMPLATE, "bob,ou=admins");
+        assert!(dn.starts_with("uid=bob\\"), "{}", dn);
+        assert!(!dn.contains("bob,ou=admins"), "{}", dn);
+        assert!(dn.ends_with(",ou=people,dc=example,dc=org"), "{}", dn);
+
+        let dn = bind_dn(TEMPLATE, "eve+cn=root");
+        assert!(!dn.contains("eve+cn=root"), "{}", dn);
+    }
+
+    #[test]
+    fn bind_dn_escapes_rfc4514_special_characters_as_hex_pairs() {
+        let escaped = |username: &str| {
+            bind_dn("uid={username}", username)
+                .strip_prefix("uid=")
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(escaped("a,b+c\"d\\e<f>g;h=i"), "a\\2cb\\2bc\\22d\\5ce\\3cf\\3eg\\3bh\\3di");
+        assert_eq!(escaped("nul\0"), "nul\\00");
+        // `#` and spaces only need escaping where they could be read as part of the syntax.
+        assert_eq!(escaped("#admins"), "\\23admins");
+        assert_eq!(escaped("a#b"), "a#b");
+        assert_eq!(escaped(" alice "), "\\20alice\\20");
+        assert_eq!(escaped("alice smith"), "alice smith");
+    }
+
+    fn ldap_config(admin_group: Option<&str>) -> LdapConfig {
+        LdapConfig {
+            // Nothing listens here, so a test that reached the server would fail with an error.
+            url: "ldap://127.0.0.1:1".to_string(),
+            user_dn: TEMPLATE.to_string(),
+            admin_group: admin_group.map(str::to_string),
+            name_attribute: "cn".to_string(),
+            timeout: Duration::from_millis(100),
+        }
+    }
+
+    // A directory holding one entry, bound to with one password.
+    struct StubDirectory {
+        dn: String,
+        password: &'static str,
+        attrs: HashMap<String, Vec<String>>,
+        binds: Vec<String>,
+        reads: Vec<String>,
+    }
+
+    impl StubDirectory {
+        fn new(username: &str, password: &'static str, attrs: &[(&str, &[&str])]) -> Self {
+            StubDirectory {
+                dn: bind_dn(TEMPLATE, username),
+                password,
+                attrs: attrs
+                    .iter()
+                    .map(|(name, values)| (name.to_string(), values.iter().map(|v| v.to_string()).collect()))
+                    .collect(),
+                binds: Vec::new(),
+                reads: Vec::new(),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LdapDirectory for StubDirectory {
+        async fn bind(&mut self, dn: &str, password: &str) -> Result<bool, ProviderError> {
+            self.binds.push(dn.to_string());
+            Ok(dn == self.dn && password == self.password)
+        }
+
+        async fn read_entry(&mut self, dn: &str, _: Vec<&str>) -> Result<Option<SearchEntry>, ProviderError> {
+            self.reads.push(dn.to_string());
+            Ok((dn == self.dn).then(|| SearchEntry {
+                dn: dn.to_string(),
+                attrs: self.attrs.clone(),
+                bin_attrs: HashMap::new(),
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn ldap_login_reads_the_name_and_admin_group_after_a_successful_bind() {
+        let config = ldap_config(Some("cn=Admins,ou=groups,dc=example,dc=org"));
+        let mut directory = StubDirectory::new(
+            "alice",
+            "correct horse",
+            &[("cn", &["Alice Liddell"]), ("memberOf", &["CN=admins,OU=groups,DC=example,DC=org"])],
+        );
+
+        let user = ldap_login(&config, &mut directory, "alice", "correct horse").await.unwrap().unwrap();
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.name, "Alice Liddell");
+        assert!(user.is_admin);
+        assert_eq!(directory.binds, vec![bind_dn(TEMPLATE, "alice")]);
+        assert_eq!(directory.reads, vec![bind_dn(TEMPLATE, "alice")]);
+    }
+
+    #[tokio::test]
+    async fn ldap_login_falls_back_to_the_username_for_members_of_no_admin_group() {
+        let config = ldap_config(Some("cn=admins,ou=groups,dc=example,dc=org"));
+        let mut directory =
+            StubDirectory::new("bob", "correct horse", &[("memberOf", &["cn=staff,ou=groups,dc=example,dc=org"])]);
+
+        let user = ldap_login(&config, &mut directory, "bob", "correct horse").await.unwrap().unwrap();
+        assert_eq!(user.name, "bob");
+        assert!(!user.is_admin);
+
+        // Without a configured admin group nobody starts as an admin.
+        let mut directory =
+            StubDirectory::new("bob", "correct horse", &[("memberOf", &["cn=admins,ou=groups,dc=example,dc=org"])]);
+        let user = ldap_login(&ldap_config(None), &mut directory, "bob", "correct horse").await.unwrap().unwrap();
+        assert!(!user.is_admin);
+    }
+
+    #[tokio::test]
+    async fn ldap_login_rejects_a_failed_bind_without_reading_the_entry() {
+        let config = ldap_config(None);
+        let mut directory = StubDirectory::new("alice", "correct horse", &[("cn", &["Alice Liddell"])]);
+
+        assert!(ldap_login(&config, &mut directory, "alice", "battery staple").await.unwrap().is_none());
+        // The escaped DN names no existing entry, so the injected RDN cannot bind as alice.
+        assert!(ldap_login(&config, &mut directory, "alice,ou=people", "correct horse").await.unwrap().is_none());
+        assert_eq!(directory.binds.len(), 2);
+        assert!(directory.reads.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ldap_provider_rejects_empty_passwords_without_binding() {
+        let provider = LdapProvider { config: ldap_config(None) };
+
+        assert!(provider.authenticate("alice", "").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,3 @@
+fn main() {
+    sandcrate_backend::run_worker();
+}
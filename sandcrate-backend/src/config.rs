@@ -0,0 +1,648 @@
+use chrono::Duration;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use crate::auth::AuthConfig;
+use crate::auth_provider::{AuthProviderConfig, LdapConfig};
+use crate::database::{DatabaseConfig, TokenRepository, UserRepository, UserRole};
+use crate::execution_logs::ExecutionLogConfig;
+use crate::jobs::JobQueueConfig;
+use crate::module_cache::ModuleCacheConfig;
+use crate::plugin::{PoolingConfig, ResourceLimits, RuntimeConfig, DEFAULT_TIMEOUT_MS};
+use crate::websocket::WebSocketConfig;
+
+// Read when `SANDCRATE_CONFIG` is not set and the file exists in the working directory.
+const DEFAULT_CONFIG_FILE: &str = "sandcrate.toml";
+
+// Secrets that have shipped in examples or older builds; tokens signed with them can be forged by anyone.
+const PLACEHOLDER_SECRETS: &[&str] = &[
+    "your-secret-key-change-in-production",
+    "your-super-secret-jwt-key-change-in-production",
+];
+
+const MIN_JWT_SECRET_LEN: usize = 32;
+
+const DEV_JWT_SECRET: &str = "sandcrate-development-secret-not-for-production";
+
+const MIB: usize = 1024 * 1024;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    InvalidValue { key: String, value: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse {}: {}", path.display(), e),
+            ConfigError::InvalidValue { key, value } => write!(f, "invalid value for {}: {:?}", key, value),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerSection,
+    auth: AuthSection,
+    ldap: LdapSection,
+    database: DatabaseSection,
+    jobs: JobsSection,
+    websocket: WebSocketSection,
+    execution_logs: ExecutionLogsSection,
+    runtime: RuntimeSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    host: Option<IpAddr>,
+    port: Option<u16>,
+    plugins_dir: Option<PathBuf>,
+    max_plugin_size_mb: Option<u64>,
+    max_execution_timeout_secs: Option<u64>,
+    dev_mode: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    jwt_secret: Option<String>,
+    jwt_expiration_minutes: Option<i64>,
+    refresh_token_expiration_days: Option<i64>,
+    default_role: Option<UserRole>,
+    provider: Option<String>,
+    pam_service: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LdapSection {
+    url: Option<String>,
+    user_dn: Option<String>,
+    admin_group: Option<String>,
+    name_attribute: Option<String>,
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseSection {
+    url: Option<String>,
+    max_connections: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct JobsSection {
+    workers: Option<usize>,
+    max_queue_depth: Option<i64>,
+    poll_interval_ms: Option<u64>,
+    lease_secs: Option<u64>,
+    max_attempts: Option<i32>,
+    worker_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WebSocketSection {
+    ping_interval_secs: Option<u64>,
+    pong_timeout_secs: Option<u64>,
+    idle_timeout_secs: Option<u64>,
+    max_connections: Option<usize>,
+    max_connections_per_user: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ExecutionLogsSection {
+    batch_size: Option<usize>,
+    flush_ms: Option<u64>,
+    retention_days: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RuntimeSection {
+    max_memory_mb: Option<usize>,
+    max_table_elements: Option<u32>,
+    cache_size: Option<usize>,
+    // An empty string keeps compiled modules in memory only.
+    cache_dir: Option<String>,
+    pooling_enabled: Option<bool>,
+    pool_max_instances: Option<u32>,
+    pool_memory_mb: Option<usize>,
+}
+
+/// Settings for the whole backend: defaults, overridden by the optional TOML file,
+/// overridden by environment variables.
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub host: IpAddr,
+    pub port: u16,
+    pub plugins_dir: PathBuf,
+    pub max_plugin_size_bytes: usize,
+    // Longest timeout a client may request for a single execution.
+    pub max_execution_timeout_ms: u64,
+    // Allows running without a real JWT secret; never enable this in a deployment.
+    pub dev_mode: bool,
+    jwt_secret: Option<String>,
+    // Access tokens are short-lived; sessions are kept alive with rotating refresh tokens.
+    pub jwt_expiration_minutes: i64,
+    pub refresh_token_expiration_days: i64,
+    // Role of accounts created on first login; system administrators always start as admin.
+    pub default_role: UserRole,
+    // How login passwords are checked: PAM, argon2 hashes in the database, or an LDAP bind.
+    pub auth_provider: AuthProviderConfig,
+    pub database: DatabaseConfig,
+    pub jobs: JobQueueConfig,
+    pub websocket: WebSocketConfig,
+    pub execution_logs: ExecutionLogConfig,
+    pub runtime: RuntimeConfig,
+}
+
+impl ServerConfig {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match std::env::var("SANDCRATE_CONFIG") {
+            Ok(path) => read_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => read_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            Err(_) => FileConfig::default(),
+        };
+
+        let mut database = DatabaseConfig::default();
+        if std::env::var("DATABASE_URL").is_err() {
+            if let Some(url) = file.database.url {
+                database.url = url;
+            }
+        }
+        if let Some(max_connections) = env_or("DATABASE_MAX_CONNECTIONS", file.database.max_connections)? {
+            database.max_connections = max_connections;
+        }
+
+        let auth_provider = load_auth_provider(file.auth.provider, file.auth.pam_service, file.ldap)?;
+
+        let max_plugin_size_mb = env_or("MAX_PLUGIN_SIZE_MB", file.server.max_plugin_size_mb)?.unwrap_or(50);
+        let plugins_dir = env_or("PLUGINS_DIR", file.server.plugins_dir)?
+            .unwrap_or_else(|| PathBuf::from("../assets/plugins"));
+        let runtime = load_runtime(file.runtime, &plugins_dir)?;
+        let max_execution_timeout_secs =
+            env_or("MAX_EXECUTION_TIMEOUT_SECS", file.server.max_execution_timeout_secs)?.unwrap_or(300);
+
+        let config = Self {
+            host: env_or("SERVER_HOST", file.server.host)?.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            port: env_or("SERVER_PORT", file.server.port)?.unwrap_or(3000),
+            plugins_dir,
+            max_plugin_size_bytes: (max_plugin_size_mb as usize).saturating_mul(1024 * 1024),
+            max_execution_timeout_ms: max_execution_timeout_secs.saturating_mul(1000),
+            dev_mode: env_or("SANDCRATE_DEV", file.server.dev_mode)?.unwrap_or(false),
+            jwt_secret: env_or("JWT_SECRET", file.auth.jwt_secret)?,
+            jwt_expiration_minutes: env_or("JWT_EXPIRATION_MINUTES", file.auth.jwt_expiration_minutes)?.unwrap_or(15),
+            refresh_token_expiration_days: env_or(
+                "REFRESH_TOKEN_EXPIRATION_DAYS",
+                file.auth.refresh_token_expiration_days,
+            )?
+            .unwrap_or(30),
+            default_role: env_or("DEFAULT_USER_ROLE", file.auth.default_role)?.unwrap_or(UserRole::User),
+            auth_provider,
+            database,
+            jobs: load_jobs(file.jobs)?,
+            websocket: load_websocket(file.websocket)?,
+            execution_logs: load_execution_logs(file.execution_logs)?,
+            runtime,
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.port == 0 {
+            return Err(ConfigError::Invalid("SERVER_PORT must not be 0".to_string()));
+        }
+        if self.max_plugin_size_bytes == 0 {
+            return Err(ConfigError::Invalid("MAX_PLUGIN_SIZE_MB must be at least 1".to_string()));
+        }
+        if self.max_execution_timeout_ms < DEFAULT_TIMEOUT_MS {
+            return Err(ConfigError::Invalid(format!(
+                "MAX_EXECUTION_TIMEOUT_SECS must be at least {}, the default execution timeout",
+                DEFAULT_TIMEOUT_MS / 1000
+            )));
+        }
+        if self.jwt_expiration_minutes <= 0 {
+            return Err(ConfigError::Invalid("JWT_EXPIRATION_MINUTES must be at least 1".to_string()));
+        }
+        if self.refresh_token_expiration_days <= 0 {
+            return Err(ConfigError::Invalid("REFRESH_TOKEN_EXPIRATION_DAYS must be at least 1".to_string()));
+        }
+        if self.plugins_dir.exists() && !self.plugins_dir.is_dir() {
+            return Err(ConfigError::Invalid(format!(
+                "PLUGINS_DIR {} is not a directory",
+                self.plugins_dir.display()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn bind_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    /// Builds the token settings, refusing missing or placeholder secrets outside dev mode.
+    pub fn auth_config(
+        &self,
+        tokens: Arc<dyn TokenRepository + Send + Sync>,
+        users: Arc<dyn UserRepository + Send + Sync>,
+    ) -> Result<AuthConfig, ConfigError> {
+        let secret = match &self.jwt_secret {
+            Some(secret) if self.dev_mode => secret.clone(),
+            Some(secret) if PLACEHOLDER_SECRETS.contains(&secret.as_str()) => {
+                return Err(ConfigError::Invalid(
+                    "JWT_SECRET is still the example placeholder; set a random secret (or SANDCRATE_DEV=true for local development)".to_string(),
+                ));
+            }
+            Some(secret) if secret.len() < MIN_JWT_SECRET_LEN => {
+                return Err(ConfigError::Invalid(format!(
+                    "JWT_SECRET must be at least {} characters long",
+                    MIN_JWT_SECRET_LEN
+                )));
+            }
+            Some(secret) => secret.clone(),
+            None if self.dev_mode => {
+                eprintln!("WARNING: JWT_SECRET is not set, using the development secret");
+                DEV_JWT_SECRET.to_string()
+            }
+            None => {
+                return Err(ConfigError::Invalid(
+                    "JWT_SECRET must be set (or SANDCRATE_DEV=true for local development)".to_string(),
+                ));
+            }
+        };
+
+        Ok(AuthConfig::new(
+            secret,
+            Duration::minutes(self.jwt_expiration_minutes),
+            Duration::days(self.refresh_token_expiration_days),
+            self.default_role,
+            self.auth_provider.build(users.clone()),
+            tokens,
+            users,
+        ))
+    }
+}
+
+fn load_auth_provider(
+    provider: Option<String>,
+    pam_service: Option<String>,
+    ldap: LdapSection,
+) -> Result<AuthProviderConfig, ConfigError> {
+    let provider = env_or("AUTH_PROVIDER", provider)?.unwrap_or_else(|| "pam".to_string()).to_lowercase();
+
+    match provider.as_str() {
+        "pam" if cfg!(feature = "pam") => Ok(AuthProviderConfig::Pam {
+            service: env_or("PAM_SERVICE", pam_service)?.unwrap_or_else(|| "login".to_string()),
+        }),
+        "pam" => Err(ConfigError::Invalid(
+            "AUTH_PROVIDER=pam needs a build with the \"pam\" feature".to_string(),
+        )),
+        "database" => Ok(AuthProviderConfig::Database),
+        "ldap" => {
+            let url = env_or("LDAP_URL", ldap.url)?
+                .ok_or_else(|| ConfigError::Invalid("LDAP_URL must be set when AUTH_PROVIDER=ldap".to_string()))?;
+            let user_dn = env_or("LDAP_USER_DN", ldap.user_dn)?
+                .ok_or_else(|| ConfigError::Invalid("LDAP_USER_DN must be set when AUTH_PROVIDER=ldap".to_string()))?;
+            if !user_dn.contains("{username}") {
+                return Err(ConfigError::InvalidValue {
+                    key: "LDAP_USER_DN".to_string(),
+                    value: user_dn,
+                });
+            }
+            Ok(AuthProviderConfig::Ldap(LdapConfig {
+                url,
+                user_dn,
+                admin_group: env_or("LDAP_ADMIN_GROUP", ldap.admin_group)?,
+                name_attribute: env_or("LDAP_NAME_ATTRIBUTE", ldap.name_attribute)?.unwrap_or_else(|| "cn".to_string()),
+                timeout: StdDuration::from_secs(env_or("LDAP_TIMEOUT_SECS", ldap.timeout_secs)?.unwrap_or(5)),
+            }))
+        }
+        _ => Err(ConfigError::InvalidValue {
+            key: "AUTH_PROVIDER".to_string(),
+            value: provider,
+        }),
+    }
+}
+
+fn load_jobs(file: JobsSection) -> Result<JobQueueConfig, ConfigError> {
+    let defaults = JobQueueConfig::default();
+    Ok(JobQueueConfig {
+        workers: env_or("JOB_WORKERS", file.workers)?.unwrap_or(defaults.workers),
+        max_queue_depth: env_at_least("JOB_MAX_QUEUE_DEPTH", file.max_queue_depth, defaults.max_queue_depth, 1)?,
+        poll_interval: StdDuration::from_millis(env_at_least(
+            "JOB_POLL_INTERVAL_MS",
+            file.poll_interval_ms,
+            defaults.poll_interval.as_millis() as u64,
+            1,
+        )?),
+        lease_duration: StdDuration::from_secs(env_at_least(
+            "JOB_LEASE_SECS",
+            file.lease_secs,
+            defaults.lease_duration.as_secs(),
+            1,
+        )?),
+        max_attempts: env_at_least("JOB_MAX_ATTEMPTS", file.max_attempts, defaults.max_attempts, 1)?,
+        worker_id: env_or("WORKER_ID", file.worker_id)?.unwrap_or(defaults.worker_id),
+    })
+}
+
+fn load_websocket(file: WebSocketSection) -> Result<WebSocketConfig, ConfigError> {
+    let defaults = WebSocketConfig::default();
+    let idle_timeout_secs = env_or(
+        "WS_IDLE_TIMEOUT_SECS",
+        file.idle_timeout_secs.or(defaults.idle_timeout.map(|idle| idle.as_secs())),
+    )?
+    .unwrap_or(0);
+    Ok(WebSocketConfig {
+        ping_interval: StdDuration::from_secs(env_at_least(
+            "WS_PING_INTERVAL_SECS",
+            file.ping_interval_secs,
+            defaults.ping_interval.as_secs(),
+            1,
+        )?),
+        pong_timeout: StdDuration::from_secs(
+            env_or("WS_PONG_TIMEOUT_SECS", file.pong_timeout_secs)?.unwrap_or(defaults.pong_timeout.as_secs()),
+        ),
+        idle_timeout: (idle_timeout_secs > 0).then(|| StdDuration::from_secs(idle_timeout_secs)),
+        max_connections: env_at_least("WS_MAX_CONNECTIONS", file.max_connections, defaults.max_connections, 1)?,
+        max_connections_per_user: env_at_least(
+            "WS_MAX_CONNECTIONS_PER_USER",
+            file.max_connections_per_user,
+            defaults.max_connections_per_user,
+            1,
+        )?,
+    })
+}
+
+fn load_execution_logs(file: ExecutionLogsSection) -> Result<ExecutionLogConfig, ConfigError> {
+    let defaults = ExecutionLogConfig::default();
+    let retention_days = env_or(
+        "EXECUTION_LOG_RETENTION_DAYS",
+        file.retention_days.or(defaults.retention.map(|retention| retention.as_secs() / SECS_PER_DAY)),
+    )?
+    .unwrap_or(0);
+    Ok(ExecutionLogConfig {
+        batch_size: env_at_least("EXECUTION_LOG_BATCH_SIZE", file.batch_size, defaults.batch_size, 1)?,
+        flush_interval: StdDuration::from_millis(env_at_least(
+            "EXECUTION_LOG_FLUSH_MS",
+            file.flush_ms,
+            defaults.flush_interval.as_millis() as u64,
+            1,
+        )?),
+        retention: (retention_days > 0).then(|| StdDuration::from_secs(retention_days.saturating_mul(SECS_PER_DAY))),
+    })
+}
+
+// The on-disk module cache lives next to the plugins unless PLUGIN_CACHE_DIR says otherwise.
+fn load_runtime(file: RuntimeSection, plugins_dir: &Path) -> Result<RuntimeConfig, ConfigError> {
+    let defaults = RuntimeConfig::default();
+
+    let max_memory_mb = env_at_least(
+        "PLUGIN_MAX_MEMORY_MB",
+        file.max_memory_mb,
+        defaults.limits.max_memory_bytes / MIB,
+        1,
+    )?;
+    let pool_memory_mb = env_at_least(
+        "PLUGIN_POOL_MEMORY_MB",
+        file.pool_memory_mb,
+        defaults.pooling.memory_bytes_per_slot / MIB,
+        1,
+    )?;
+    let pooling_enabled = env_or("PLUGIN_POOLING_ENABLED", file.pooling_enabled)?.unwrap_or(defaults.pooling.enabled);
+    if pooling_enabled && pool_memory_mb < max_memory_mb {
+        return Err(ConfigError::Invalid(
+            "PLUGIN_POOL_MEMORY_MB must be at least PLUGIN_MAX_MEMORY_MB when pooling is enabled".to_string(),
+        ));
+    }
+
+    // An empty PLUGIN_CACHE_DIR disables the on-disk cache, which `env_or` would treat as unset.
+    let cache_dir = match std::env::var("PLUGIN_CACHE_DIR").ok().or(file.cache_dir) {
+        Some(dir) if dir.trim().is_empty() => None,
+        Some(dir) => Some(PathBuf::from(dir.trim())),
+        None => Some(plugins_dir.join(".cache")),
+    };
+
+    Ok(RuntimeConfig {
+        limits: ResourceLimits {
+            max_memory_bytes: max_memory_mb.saturating_mul(MIB),
+            max_table_elements: env_at_least(
+                "PLUGIN_MAX_TABLE_ELEMENTS",
+                file.max_table_elements,
+                defaults.limits.max_table_elements,
+                1,
+            )?,
+        },
+        pooling: PoolingConfig {
+            enabled: pooling_enabled,
+            max_instances: env_at_least(
+                "PLUGIN_POOL_MAX_INSTANCES",
+                file.pool_max_instances,
+                defaults.pooling.max_instances,
+                1,
+            )?,
+            memory_bytes_per_slot: pool_memory_mb.saturating_mul(MIB),
+        },
+        module_cache: ModuleCacheConfig {
+            capacity: env_at_least("PLUGIN_CACHE_SIZE", file.cache_size, defaults.module_cache.capacity, 1)?,
+            cache_dir,
+        },
+    })
+}
+
+// Like `env_or` with a default; the minimum applies to a value from the file as well.
+fn env_at_least<T: FromStr + PartialOrd + fmt::Display>(
+    key: &str,
+    fallback: Option<T>,
+    default: T,
+    min: T,
+) -> Result<T, ConfigError> {
+    match env_or(key, fallback)? {
+        Some(value) if value < min => Err(ConfigError::Invalid(format!("{} must be at least {}", key, min))),
+        Some(value) => Ok(value),
+        None => Ok(default),
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+}
+
+// An environment variable wins over the file; a set but unparsable one is an error, not a silent default.
+fn env_or<T: FromStr>(key: &str, fallback: Option<T>) -> Result<Option<T>, ConfigError> {
+    match std::env::var(key) {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidValue {
+                key: key.to_string(),
+                value,
+            }),
+        _ => Ok(fallback),
+    }
+}
+
+// Built-in defaults without reading the file or environment, for tests of other modules.
+#[cfg(test)]
+impl ServerConfig {
+    pub(crate) fn for_tests() -> Self {
+        ServerConfig {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+            plugins_dir: PathBuf::from("../assets/plugins"),
+            max_plugin_size_bytes: 50 * MIB,
+            max_execution_timeout_ms: 300_000,
+            dev_mode: false,
+            jwt_secret: None,
+            jwt_expiration_minutes: 15,
+            refresh_token_expiration_days: 30,
+            default_role: UserRole::User,
+            auth_provider: AuthProviderConfig::Database,
+            database: DatabaseConfig::default(),
+            jobs: JobQueueConfig::default(),
+            websocket: WebSocketConfig::default(),
+            execution_logs: ExecutionLogConfig::default(),
+            runtime: RuntimeConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ServerConfig {
+        ServerConfig::for_tests()
+    }
+
+    fn rejects(change: impl FnOnce(&mut ServerConfig)) -> ConfigError {
+        let mut config = config();
+        change(&mut config);
+        config.validate().expect_err("configuration should be rejected")
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        assert!(config().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        assert!(matches!(rejects(|c| c.port = 0), ConfigError::Invalid(_)));
+        assert!(matches!(rejects(|c| c.max_plugin_size_bytes = 0), ConfigError::Invalid(_)));
+        assert!(matches!(rejects(|c| c.max_execution_timeout_ms = DEFAULT_TIMEOUT_MS - 1), ConfigError::Invalid(_)));
+        assert!(matches!(rejects(|c| c.jwt_expiration_minutes = 0), ConfigError::Invalid(_)));
+        assert!(matches!(rejects(|c| c.refresh_token_expiration_days = -1), ConfigError::Invalid(_)));
+    }
+
+    #[test]
+    fn rejects_a_plugins_dir_that_is_a_file() {
+        assert!(matches!(rejects(|c| c.plugins_dir = PathBuf::from("Cargo.toml")), ConfigError::Invalid(_)));
+    }
+
+    // Every test below uses its own variable, so they can run in parallel.
+
+    #[test]
+    fn env_or_prefers_the_environment_and_falls_back_when_unset_or_blank() {
+        assert_eq!(env_or("SANDCRATE_TEST_UNSET", Some(7u16)).unwrap(), Some(7));
+
+        std::env::set_var("SANDCRATE_TEST_SET", " 42 ");
+        assert_eq!(env_or("SANDCRATE_TEST_SET", Some(7u16)).unwrap(), Some(42));
+
+        std::env::set_var("SANDCRATE_TEST_BLANK", "  ");
+        assert_eq!(env_or("SANDCRATE_TEST_BLANK", Some(7u16)).unwrap(), Some(7));
+    }
+
+    #[test]
+    fn env_or_rejects_unparsable_values() {
+        std::env::set_var("SANDCRATE_TEST_UNPARSABLE", "lots");
+        match env_or::<u16>("SANDCRATE_TEST_UNPARSABLE", Some(7)) {
+            Err(ConfigError::InvalidValue { key, value }) => {
+                assert_eq!(key, "SANDCRATE_TEST_UNPARSABLE");
+                assert_eq!(value, "lots");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn env_at_least_enforces_the_minimum() {
+        assert_eq!(env_at_least("SANDCRATE_TEST_MIN_UNSET", None, 5u64, 1).unwrap(), 5);
+        assert_eq!(env_at_least("SANDCRATE_TEST_MIN_UNSET", Some(3), 5u64, 1).unwrap(), 3);
+        assert!(matches!(env_at_least("SANDCRATE_TEST_MIN_UNSET", Some(0), 5u64, 1), Err(ConfigError::Invalid(_))));
+
+        std::env::set_var("SANDCRATE_TEST_MIN_OK", "1");
+        assert_eq!(env_at_least("SANDCRATE_TEST_MIN_OK", Some(3), 5u64, 1).unwrap(), 1);
+
+        std::env::set_var("SANDCRATE_TEST_MIN_LOW", "0");
+        assert!(matches!(env_at_least("SANDCRATE_TEST_MIN_LOW", None, 5u64, 1), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn reads_the_tuning_sections_from_the_file() {
+        let file: FileConfig = toml::from_str(
+            r#"
+            [jobs]
+            max_queue_depth = 50
+            lease_secs = 90
+
+            [websocket]
+            idle_timeout_secs = 0
+            max_connections_per_user = 2
+
+            [execution_logs]
+            retention_days = 7
+
+            [runtime]
+            max_memory_mb = 64
+            cache_dir = ""
+            "#,
+        )
+        .unwrap();
+
+        // None of these keys is set in the environment of the tests.
+        let jobs = load_jobs(file.jobs).unwrap();
+        assert_eq!(jobs.max_queue_depth, 50);
+        assert_eq!(jobs.lease_duration, StdDuration::from_secs(90));
+        let websocket = load_websocket(file.websocket).unwrap();
+        assert_eq!(websocket.idle_timeout, None);
+        assert_eq!(websocket.max_connections_per_user, 2);
+        let logs = load_execution_logs(file.execution_logs).unwrap();
+        assert_eq!(logs.retention, Some(StdDuration::from_secs(7 * SECS_PER_DAY)));
+        let runtime = load_runtime(file.runtime, Path::new("plugins")).unwrap();
+        assert_eq!(runtime.limits.max_memory_bytes, 64 * MIB);
+        assert_eq!(runtime.module_cache.cache_dir, None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_out_of_range_file_values() {
+        assert!(toml::from_str::<FileConfig>("[jobs]\nqueue_depth = 5").is_err());
+
+        let file: FileConfig = toml::from_str("[execution_logs]\nbatch_size = 0").unwrap();
+        assert!(matches!(load_execution_logs(file.execution_logs), Err(ConfigError::Invalid(_))));
+    }
+}
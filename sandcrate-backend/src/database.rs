@@ -1,4 +1,4 @@
-use sqlx::{PgPool, PgPoolOptions, postgres::PgPoolOptions as PgPoolOptionsPostgres};
+use sqlx::{PgPool, postgres::PgPoolOptions};
 use std::time::Duration;
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
@@ -32,7 +32,7 @@ pub async fn create_pool(config: &DatabaseConfig) -> Result<PgPool, sqlx::Error>
     PgPoolOptions::new()
         .max_connections(config.max_connections)
         .min_connections(config.min_connections)
-        .connect_timeout(config.connect_timeout)
+        .acquire_timeout(config.connect_timeout)
         .idle_timeout(config.idle_timeout)
         .max_lifetime(config.max_lifetime)
         .connect(&config.url)
@@ -57,9 +57,11 @@ pub struct Plugin {
     pub last_executed_at: Option<DateTime<Utc>>,
     pub execution_count: i32,
     pub average_execution_time_ms: Option<i64>,
+    pub max_memory_bytes: Option<i64>,
+    pub max_table_elements: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "plugin_status", rename_all = "lowercase")]
 pub enum PluginStatus {
     Active,
@@ -68,7 +70,18 @@ pub enum PluginStatus {
     Processing,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+impl PluginStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PluginStatus::Active => "active",
+            PluginStatus::Inactive => "inactive",
+            PluginStatus::Error => "error",
+            PluginStatus::Processing => "processing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct PluginExecution {
     pub id: Uuid,
     pub plugin_id: Uuid,
@@ -81,18 +94,37 @@ pub struct PluginExecution {
     pub status: ExecutionStatus,
     pub started_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
+    pub peak_memory_bytes: Option<i64>,
+    pub stderr: Option<String>,
+    // Set when the plugin ran to completion, whatever its exit code.
+    pub exit_code: Option<i32>,
+    pub output_truncated: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "execution_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum ExecutionStatus {
     Running,
     Completed,
     Failed,
     Cancelled,
+    Timeout,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+impl ExecutionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecutionStatus::Running => "running",
+            ExecutionStatus::Completed => "completed",
+            ExecutionStatus::Failed => "failed",
+            ExecutionStatus::Cancelled => "cancelled",
+            ExecutionStatus::Timeout => "timeout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
@@ -105,14 +137,191 @@ pub struct User {
     pub last_login_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum UserRole {
     Admin,
     User,
     Guest,
 }
 
+impl UserRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Admin => "admin",
+            UserRole::User => "user",
+            UserRole::Guest => "guest",
+        }
+    }
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "admin" => Ok(UserRole::Admin),
+            "user" => Ok(UserRole::User),
+            "guest" => Ok(UserRole::Guest),
+            other => Err(format!("unknown role '{}'", other)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait UserRepository {
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, sqlx::Error>;
+    // Creates the user with `role` on first login; later logins keep the stored role.
+    async fn upsert_user_on_login(&self, username: &str, name: &str, role: UserRole) -> Result<User, sqlx::Error>;
+    async fn list_users(&self) -> Result<Vec<User>, sqlx::Error>;
+    async fn set_user_role(&self, username: &str, role: UserRole) -> Result<Option<User>, sqlx::Error>;
+    async fn count_active_admins(&self) -> Result<i64, sqlx::Error>;
+    async fn create_user(
+        &self,
+        username: &str,
+        name: &str,
+        role: UserRole,
+        password_hash: Option<&str>,
+    ) -> Result<User, sqlx::Error>;
+    async fn get_password_hash(&self, username: &str) -> Result<Option<String>, sqlx::Error>;
+    async fn set_password_hash(&self, username: &str, password_hash: &str) -> Result<bool, sqlx::Error>;
+}
+
+pub struct PostgresUserRepository {
+    pool: PgPool,
+}
+
+impl PostgresUserRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, name, role as "role: UserRole", is_active,
+                   created_at, updated_at, last_login_at
+            FROM users WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn upsert_user_on_login(&self, username: &str, name: &str, role: UserRole) -> Result<User, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            INSERT INTO users (username, name, role, last_login_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (username) DO UPDATE
+            SET name = EXCLUDED.name, last_login_at = NOW()
+            RETURNING id, username, email, name, role as "role: UserRole", is_active,
+                      created_at, updated_at, last_login_at
+            "#,
+            username,
+            name,
+            role as UserRole
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, name, role as "role: UserRole", is_active,
+                   created_at, updated_at, last_login_at
+            FROM users ORDER BY username
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn set_user_role(&self, username: &str, role: UserRole) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users SET role = $2 WHERE username = $1
+            RETURNING id, username, email, name, role as "role: UserRole", is_active,
+                      created_at, updated_at, last_login_at
+            "#,
+            username,
+            role as UserRole
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn count_active_admins(&self) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM users WHERE role = 'admin' AND is_active"#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        name: &str,
+        role: UserRole,
+        password_hash: Option<&str>,
+    ) -> Result<User, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            INSERT INTO users (username, name, role, password_hash)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, username, email, name, role as "role: UserRole", is_active,
+                      created_at, updated_at, last_login_at
+            "#,
+            username,
+            name,
+            role as UserRole,
+            password_hash
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn get_password_hash(&self, username: &str) -> Result<Option<String>, sqlx::Error> {
+        let hash = sqlx::query_scalar!(
+            "SELECT password_hash FROM users WHERE username = $1",
+            username
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(hash.flatten())
+    }
+
+    async fn set_password_hash(&self, username: &str, password_hash: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE users SET password_hash = $2 WHERE username = $1",
+            username,
+            password_hash
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// Executions run in whichever process claimed them; cancel requests are broadcast to all of them.
+pub const EXECUTION_CANCEL_CHANNEL: &str = "plugin_execution_cancel";
+
+// Notified with the execution id whenever output lines are stored, so followers need not poll.
+pub const EXECUTION_LOGS_CHANNEL: &str = "plugin_execution_logs";
+
 #[async_trait::async_trait]
 pub trait PluginRepository {
     async fn create_plugin(&self, plugin: CreatePluginRequest) -> Result<Plugin, sqlx::Error>;
@@ -122,7 +331,16 @@ pub trait PluginRepository {
     async fn update_plugin(&self, id: Uuid, updates: UpdatePluginRequest) -> Result<Plugin, sqlx::Error>;
     async fn delete_plugin(&self, id: Uuid) -> Result<bool, sqlx::Error>;
     async fn record_execution(&self, execution: CreateExecutionRequest) -> Result<PluginExecution, sqlx::Error>;
-    async fn get_execution_history(&self, plugin_id: Uuid, limit: Option<i64>) -> Result<Vec<PluginExecution>, sqlx::Error>;
+    async fn complete_execution(&self, id: Uuid, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error>;
+    async fn fail_execution(&self, id: Uuid, status: ExecutionStatus, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error>;
+    async fn cancel_execution(&self, id: Uuid, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error>;
+    async fn get_execution(&self, id: Uuid) -> Result<Option<PluginExecution>, sqlx::Error>;
+    async fn get_execution_by_session(&self, session_id: &str) -> Result<Option<PluginExecution>, sqlx::Error>;
+    async fn request_execution_cancel(&self, id: Uuid) -> Result<(), sqlx::Error>;
+    async fn get_execution_history(&self, plugin_id: Uuid, filter: ExecutionHistoryFilter) -> Result<ExecutionPage, sqlx::Error>;
+    async fn append_execution_logs(&self, lines: Vec<NewExecutionLogLine>) -> Result<(), sqlx::Error>;
+    async fn get_execution_logs(&self, execution_id: Uuid, filter: ExecutionLogFilter) -> Result<Vec<ExecutionLogLine>, sqlx::Error>;
+    async fn delete_execution_logs_before(&self, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error>;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -145,6 +363,8 @@ pub struct UpdatePluginRequest {
     pub author: Option<String>,
     pub tags: Option<Vec<String>>,
     pub status: Option<PluginStatus>,
+    pub max_memory_bytes: Option<i64>,
+    pub max_table_elements: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -155,6 +375,96 @@ pub struct CreateExecutionRequest {
     pub parameters: Option<serde_json::Value>,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FinishExecutionRequest {
+    pub result: Option<String>,
+    pub stderr: Option<String>,
+    pub exit_code: Option<i32>,
+    pub output_truncated: bool,
+    pub error: Option<String>,
+    pub execution_time_ms: i64,
+    pub peak_memory_bytes: Option<i64>,
+}
+
+// Position in the (started_at DESC, id DESC) ordering of an execution history.
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutionCursor {
+    pub started_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl ExecutionCursor {
+    pub fn encode(&self) -> String {
+        format!("{}_{}", self.started_at.timestamp_micros(), self.id)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (micros, id) = cursor.split_once('_')?;
+        Some(Self {
+            started_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExecutionHistoryFilter {
+    pub status: Option<ExecutionStatus>,
+    pub user_id: Option<Uuid>,
+    pub started_after: Option<DateTime<Utc>>,
+    pub started_before: Option<DateTime<Utc>>,
+    pub cursor: Option<ExecutionCursor>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExecutionPage {
+    pub executions: Vec<PluginExecution>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ExecutionLogLine {
+    pub id: i64,
+    pub execution_id: Uuid,
+    pub seq: i64,
+    pub stream: String,
+    pub logged_at: DateTime<Utc>,
+    pub line: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewExecutionLogLine {
+    pub execution_id: Uuid,
+    pub seq: i64,
+    pub stream: LogStream,
+    pub logged_at: DateTime<Utc>,
+    pub line: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ExecutionLogFilter {
+    pub stream: Option<LogStream>,
+    pub after_seq: Option<i64>,
+    pub limit: Option<i64>,
+}
+
 pub struct PostgresPluginRepository {
     pool: PgPool,
 }
@@ -163,6 +473,56 @@ impl PostgresPluginRepository {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
+
+    // Moves a running execution to its final state and folds it into the plugin's statistics
+    // in one transaction, so the two can never disagree.
+    async fn finish_execution(&self, id: Uuid, status: ExecutionStatus, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        let execution = sqlx::query_as!(
+            PluginExecution,
+            r#"
+            UPDATE plugin_executions
+            SET status = $2, result = $3, error = $4, execution_time_ms = $5, peak_memory_bytes = $6, completed_at = $7,
+                stderr = $8, exit_code = $9, output_truncated = $10
+            WHERE id = $1 AND status = 'running'
+            RETURNING id, plugin_id, user_id, session_id, parameters, result, error, execution_time_ms,
+                   status as "status: ExecutionStatus", started_at, completed_at, peak_memory_bytes, stderr,
+                   exit_code, output_truncated
+            "#,
+            id,
+            status as ExecutionStatus,
+            outcome.result,
+            outcome.error,
+            outcome.execution_time_ms,
+            outcome.peak_memory_bytes,
+            now,
+            outcome.stderr,
+            outcome.exit_code,
+            outcome.output_truncated
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE plugins
+            SET execution_count = execution_count + 1,
+                average_execution_time_ms = (COALESCE(average_execution_time_ms, 0) * execution_count + $2) / (execution_count + 1),
+                last_executed_at = $3
+            WHERE id = $1
+            "#,
+            execution.plugin_id,
+            outcome.execution_time_ms,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(execution)
+    }
 }
 
 #[async_trait::async_trait]
@@ -176,7 +536,9 @@ impl PluginRepository for PostgresPluginRepository {
             r#"
             INSERT INTO plugins (id, name, filename, file_path, file_size, description, version, author, tags, status, created_at, updated_at)
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-            RETURNING *
+            RETURNING id, name, filename, file_path, file_size, description, version, author,
+                   COALESCE(tags, '{}') as "tags!", status as "status: PluginStatus", created_at, updated_at,
+                   last_executed_at, execution_count, average_execution_time_ms, max_memory_bytes, max_table_elements
             "#,
             id,
             plugin.name,
@@ -198,7 +560,12 @@ impl PluginRepository for PostgresPluginRepository {
     async fn get_plugin_by_id(&self, id: Uuid) -> Result<Option<Plugin>, sqlx::Error> {
         sqlx::query_as!(
             Plugin,
-            "SELECT * FROM plugins WHERE id = $1",
+            r#"
+            SELECT id, name, filename, file_path, file_size, description, version, author,
+                   COALESCE(tags, '{}') as "tags!", status as "status: PluginStatus", created_at, updated_at,
+                   last_executed_at, execution_count, average_execution_time_ms, max_memory_bytes, max_table_elements
+            FROM plugins WHERE id = $1
+            "#,
             id
         )
         .fetch_optional(&self.pool)
@@ -208,7 +575,12 @@ impl PluginRepository for PostgresPluginRepository {
     async fn get_plugin_by_filename(&self, filename: &str) -> Result<Option<Plugin>, sqlx::Error> {
         sqlx::query_as!(
             Plugin,
-            "SELECT * FROM plugins WHERE filename = $1",
+            r#"
+            SELECT id, name, filename, file_path, file_size, description, version, author,
+                   COALESCE(tags, '{}') as "tags!", status as "status: PluginStatus", created_at, updated_at,
+                   last_executed_at, execution_count, average_execution_time_ms, max_memory_bytes, max_table_elements
+            FROM plugins WHERE filename = $1
+            "#,
             filename
         )
         .fetch_optional(&self.pool)
@@ -221,7 +593,12 @@ impl PluginRepository for PostgresPluginRepository {
         
         sqlx::query_as!(
             Plugin,
-            "SELECT * FROM plugins ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+            r#"
+            SELECT id, name, filename, file_path, file_size, description, version, author,
+                   COALESCE(tags, '{}') as "tags!", status as "status: PluginStatus", created_at, updated_at,
+                   last_executed_at, execution_count, average_execution_time_ms, max_memory_bytes, max_table_elements
+            FROM plugins ORDER BY created_at DESC LIMIT $1 OFFSET $2
+            "#,
             limit,
             offset
         )
@@ -232,52 +609,45 @@ impl PluginRepository for PostgresPluginRepository {
     async fn update_plugin(&self, id: Uuid, updates: UpdatePluginRequest) -> Result<Plugin, sqlx::Error> {
         let now = Utc::now();
         
-        let mut query = String::from("UPDATE plugins SET updated_at = $1");
-        let mut params: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = vec![Box::new(now)];
-        let mut param_count = 1;
+        let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new("UPDATE plugins SET updated_at = ");
+        query.push_bind(now);
 
         if let Some(name) = updates.name {
-            param_count += 1;
-            query.push_str(&format!(", name = ${}", param_count));
-            params.push(Box::new(name));
+            query.push(", name = ").push_bind(name);
         }
 
         if let Some(description) = updates.description {
-            param_count += 1;
-            query.push_str(&format!(", description = ${}", param_count));
-            params.push(Box::new(description));
+            query.push(", description = ").push_bind(description);
         }
 
         if let Some(version) = updates.version {
-            param_count += 1;
-            query.push_str(&format!(", version = ${}", param_count));
-            params.push(Box::new(version));
+            query.push(", version = ").push_bind(version);
         }
 
         if let Some(author) = updates.author {
-            param_count += 1;
-            query.push_str(&format!(", author = ${}", param_count));
-            params.push(Box::new(author));
+            query.push(", author = ").push_bind(author);
         }
 
         if let Some(tags) = updates.tags {
-            param_count += 1;
-            query.push_str(&format!(", tags = ${}", param_count));
-            params.push(Box::new(tags));
+            query.push(", tags = ").push_bind(tags);
         }
 
         if let Some(status) = updates.status {
-            param_count += 1;
-            query.push_str(&format!(", status = ${}", param_count));
-            params.push(Box::new(status));
+            query.push(", status = ").push_bind(status);
+        }
+
+        if let Some(max_memory_bytes) = updates.max_memory_bytes {
+            query.push(", max_memory_bytes = ").push_bind(max_memory_bytes);
+        }
+
+        if let Some(max_table_elements) = updates.max_table_elements {
+            query.push(", max_table_elements = ").push_bind(max_table_elements);
         }
 
-        param_count += 1;
-        query.push_str(&format!(" WHERE id = ${} RETURNING *", param_count));
-        params.push(Box::new(id));
+        query.push(" WHERE id = ").push_bind(id);
+        query.push(" RETURNING id, name, filename, file_path, file_size, description, version, author, COALESCE(tags, '{}') AS tags, status, created_at, updated_at, last_executed_at, execution_count, average_execution_time_ms, max_memory_bytes, max_table_elements");
 
-        sqlx::query_as::<_, Plugin>(&query)
-            .bind_all(params)
+        query.build_query_as::<Plugin>()
             .fetch_one(&self.pool)
             .await
     }
@@ -302,7 +672,9 @@ impl PluginRepository for PostgresPluginRepository {
             r#"
             INSERT INTO plugin_executions (id, plugin_id, user_id, session_id, parameters, status, started_at)
             VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING *
+            RETURNING id, plugin_id, user_id, session_id, parameters, result, error, execution_time_ms,
+                   status as "status: ExecutionStatus", started_at, completed_at, peak_memory_bytes, stderr,
+                   exit_code, output_truncated
             "#,
             id,
             execution.plugin_id,
@@ -316,16 +688,988 @@ impl PluginRepository for PostgresPluginRepository {
         .await
     }
 
-    async fn get_execution_history(&self, plugin_id: Uuid, limit: Option<i64>) -> Result<Vec<PluginExecution>, sqlx::Error> {
-        let limit = limit.unwrap_or(50);
-        
+    async fn complete_execution(&self, id: Uuid, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+        self.finish_execution(id, ExecutionStatus::Completed, outcome).await
+    }
+
+    async fn fail_execution(&self, id: Uuid, status: ExecutionStatus, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+        self.finish_execution(id, status, outcome).await
+    }
+
+    async fn cancel_execution(&self, id: Uuid, outcome: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+        self.finish_execution(id, ExecutionStatus::Cancelled, outcome).await
+    }
+
+    async fn get_execution(&self, id: Uuid) -> Result<Option<PluginExecution>, sqlx::Error> {
         sqlx::query_as!(
             PluginExecution,
-            "SELECT * FROM plugin_executions WHERE plugin_id = $1 ORDER BY started_at DESC LIMIT $2",
+            r#"
+            SELECT id, plugin_id, user_id, session_id, parameters, result, error, execution_time_ms,
+                   status as "status: ExecutionStatus", started_at, completed_at, peak_memory_bytes, stderr,
+                   exit_code, output_truncated
+            FROM plugin_executions WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn get_execution_by_session(&self, session_id: &str) -> Result<Option<PluginExecution>, sqlx::Error> {
+        sqlx::query_as!(
+            PluginExecution,
+            r#"
+            SELECT id, plugin_id, user_id, session_id, parameters, result, error, execution_time_ms,
+                   status as "status: ExecutionStatus", started_at, completed_at, peak_memory_bytes, stderr,
+                   exit_code, output_truncated
+            FROM plugin_executions WHERE session_id = $1 ORDER BY started_at DESC LIMIT 1
+            "#,
+            session_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn request_execution_cancel(&self, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!("SELECT pg_notify($1, $2)", EXECUTION_CANCEL_CHANNEL, id.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_execution_history(&self, plugin_id: Uuid, filter: ExecutionHistoryFilter) -> Result<ExecutionPage, sqlx::Error> {
+        let limit = filter.limit.unwrap_or(50).clamp(1, 200);
+        
+        // One extra row tells us whether there is a next page.
+        let mut executions = sqlx::query_as!(
+            PluginExecution,
+            r#"
+            SELECT id, plugin_id, user_id, session_id, parameters, result, error, execution_time_ms,
+                   status as "status: ExecutionStatus", started_at, completed_at, peak_memory_bytes, stderr,
+                   exit_code, output_truncated
+            FROM plugin_executions
+            WHERE plugin_id = $1
+              AND ($2::execution_status IS NULL OR status = $2)
+              AND ($3::uuid IS NULL OR user_id = $3)
+              AND ($4::timestamptz IS NULL OR started_at >= $4)
+              AND ($5::timestamptz IS NULL OR started_at < $5)
+              AND ($6::timestamptz IS NULL OR (started_at, id) < ($6, $7))
+            ORDER BY started_at DESC, id DESC
+            LIMIT $8
+            "#,
             plugin_id,
+            filter.status as Option<ExecutionStatus>,
+            filter.user_id,
+            filter.started_after,
+            filter.started_before,
+            filter.cursor.map(|c| c.started_at),
+            filter.cursor.map(|c| c.id),
+            limit + 1
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let next_cursor = if executions.len() as i64 > limit {
+            executions.truncate(limit as usize);
+            executions.last().map(|e| ExecutionCursor { started_at: e.started_at, id: e.id }.encode())
+        } else {
+            None
+        };
+
+        Ok(ExecutionPage { executions, next_cursor })
+    }
+
+    async fn append_execution_logs(&self, lines: Vec<NewExecutionLogLine>) -> Result<(), sqlx::Error> {
+        let mut execution_ids = Vec::with_capacity(lines.len());
+        let mut seqs = Vec::with_capacity(lines.len());
+        let mut streams = Vec::with_capacity(lines.len());
+        let mut logged_ats = Vec::with_capacity(lines.len());
+        let mut texts = Vec::with_capacity(lines.len());
+        let mut notify_ids: Vec<Uuid> = Vec::new();
+        for line in lines {
+            if !notify_ids.contains(&line.execution_id) {
+                notify_ids.push(line.execution_id);
+            }
+            execution_ids.push(line.execution_id);
+            seqs.push(line.seq);
+            streams.push(line.stream.as_str().to_string());
+            logged_ats.push(line.logged_at);
+            texts.push(line.line);
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO plugin_execution_logs (execution_id, seq, stream, logged_at, line)
+            SELECT * FROM UNNEST($1::uuid[], $2::bigint[], $3::text[], $4::timestamptz[], $5::text[])
+            ON CONFLICT (execution_id, seq) DO NOTHING
+            "#,
+            &execution_ids,
+            &seqs,
+            &streams,
+            &logged_ats,
+            &texts
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for id in notify_ids {
+            sqlx::query!("SELECT pg_notify($1, $2)", EXECUTION_LOGS_CHANNEL, id.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_execution_logs(&self, execution_id: Uuid, filter: ExecutionLogFilter) -> Result<Vec<ExecutionLogLine>, sqlx::Error> {
+        let limit = filter.limit.unwrap_or(500).clamp(1, 5000);
+
+        sqlx::query_as!(
+            ExecutionLogLine,
+            r#"
+            SELECT * FROM plugin_execution_logs
+            WHERE execution_id = $1
+              AND ($2::text IS NULL OR stream = $2)
+              AND ($3::bigint IS NULL OR seq > $3)
+            ORDER BY seq
+            LIMIT $4
+            "#,
+            execution_id,
+            filter.stream.map(|s| s.as_str()),
+            filter.after_seq,
             limit
         )
         .fetch_all(&self.pool)
         .await
     }
-} 
\ No newline at end of file
+
+    async fn delete_execution_logs_before(&self, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM plugin_execution_logs WHERE logged_at < $1",
+            cutoff
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+pub const JOB_QUEUED_CHANNEL: &str = "plugin_jobs_queued";
+pub const JOB_FINISHED_CHANNEL: &str = "plugin_jobs_finished";
+pub const JOB_INPUT_CHANNEL: &str = "plugin_jobs_input";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "job_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PluginJob {
+    pub id: Uuid,
+    pub plugin_id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub parameters: Option<serde_json::Value>,
+    pub timeout_ms: Option<i64>,
+    pub status: JobStatus,
+    pub execution_id: Option<Uuid>,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub worker_id: Option<String>,
+    pub heartbeat_at: Option<DateTime<Utc>>,
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    // Set for jobs started from a WebSocket session, which get stdin from the client.
+    pub session_id: Option<String>,
+    pub stdin_closed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateJobRequest {
+    pub plugin_id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub parameters: Option<serde_json::Value>,
+    pub timeout_ms: Option<i64>,
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum EnqueueOutcome {
+    Queued(Box<PluginJob>),
+    // The queue already held this many jobs, so nothing was inserted.
+    Full(i64),
+}
+
+/// A chunk of stdin waiting to be handed to a running job's plugin.
+#[derive(Debug, Clone)]
+pub struct JobInput {
+    pub id: i64,
+    pub data: Vec<u8>,
+    pub eof: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobInputOutcome {
+    Accepted,
+    // The plugin has not caught up with the input already queued.
+    Full,
+    // Stdin was closed or the job has finished.
+    Closed,
+}
+
+// Advisory lock serializing enqueues, so concurrent requests cannot both pass the depth check.
+const JOB_QUEUE_LOCK: i64 = 0x7361_6e64_6a6f_6273; // "sandjobs"
+
+#[async_trait::async_trait]
+pub trait JobRepository {
+    async fn enqueue_job(&self, job: CreateJobRequest, max_depth: i64) -> Result<EnqueueOutcome, sqlx::Error>;
+    async fn get_job(&self, id: Uuid) -> Result<Option<PluginJob>, sqlx::Error>;
+    async fn claim_next_job(&self, worker_id: &str, lease: Duration) -> Result<Option<PluginJob>, sqlx::Error>;
+    // A lease is identified by the worker and the attempt it claimed, so a job requeued and
+    // claimed again by the same process is not mistaken for the earlier run.
+    async fn heartbeat_job(&self, id: Uuid, worker_id: &str, attempt: i32, lease: Duration) -> Result<bool, sqlx::Error>;
+    async fn set_job_execution(&self, id: Uuid, worker_id: &str, attempt: i32, execution_id: Uuid) -> Result<bool, sqlx::Error>;
+    async fn finish_job(&self, id: Uuid, worker_id: &str, attempt: i32, status: JobStatus, execution_id: Option<Uuid>, error: Option<String>) -> Result<Option<PluginJob>, sqlx::Error>;
+    async fn requeue_expired_jobs(&self, max_attempts: i32) -> Result<u64, sqlx::Error>;
+    // `None` closes stdin; data is refused once `max_pending` chunks wait for the plugin.
+    async fn write_job_input(&self, id: Uuid, data: Option<Vec<u8>>, max_pending: i64) -> Result<JobInputOutcome, sqlx::Error>;
+    async fn pending_job_input(&self, id: Uuid, limit: i64) -> Result<Vec<JobInput>, sqlx::Error>;
+    async fn delete_job_input(&self, id: Uuid, up_to: i64) -> Result<(), sqlx::Error>;
+}
+
+pub struct PostgresJobRepository {
+    pool: PgPool,
+}
+
+impl PostgresJobRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl JobRepository for PostgresJobRepository {
+    async fn enqueue_job(&self, job: CreateJobRequest, max_depth: i64) -> Result<EnqueueOutcome, sqlx::Error> {
+        let id = Uuid::new_v4();
+        let mut tx = self.pool.begin().await?;
+
+        // Held until commit, so the count below still holds when the row is inserted.
+        sqlx::query!("SELECT pg_advisory_xact_lock($1)", JOB_QUEUE_LOCK)
+            .execute(&mut *tx)
+            .await?;
+
+        let depth = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!" FROM plugin_jobs WHERE status = 'queued'"#
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if depth >= max_depth {
+            return Ok(EnqueueOutcome::Full(depth));
+        }
+
+        let job = sqlx::query_as!(
+            PluginJob,
+            r#"
+            INSERT INTO plugin_jobs (id, plugin_id, user_id, parameters, timeout_ms, status, session_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, plugin_id, user_id, parameters, timeout_ms, status as "status: JobStatus", execution_id,
+                   attempts, error, created_at, updated_at, started_at, completed_at, worker_id, heartbeat_at,
+                   lease_expires_at, session_id, stdin_closed
+            "#,
+            id,
+            job.plugin_id,
+            job.user_id,
+            job.parameters,
+            job.timeout_ms,
+            JobStatus::Queued as JobStatus,
+            job.session_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Delivered on commit, so workers never wake up before the row is visible.
+        sqlx::query!("SELECT pg_notify($1, $2)", JOB_QUEUED_CHANNEL, id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(EnqueueOutcome::Queued(Box::new(job)))
+    }
+
+    async fn get_job(&self, id: Uuid) -> Result<Option<PluginJob>, sqlx::Error> {
+        sqlx::query_as!(
+            PluginJob,
+            r#"
+            SELECT id, plugin_id, user_id, parameters, timeout_ms, status as "status: JobStatus", execution_id,
+                   attempts, error, created_at, updated_at, started_at, completed_at, worker_id, heartbeat_at,
+                   lease_expires_at, session_id, stdin_closed
+            FROM plugin_jobs WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn claim_next_job(&self, worker_id: &str, lease: Duration) -> Result<Option<PluginJob>, sqlx::Error> {
+        let now = Utc::now();
+        let lease_expires_at = now + chrono::Duration::milliseconds(lease.as_millis() as i64);
+
+        sqlx::query_as!(
+            PluginJob,
+            r#"
+            UPDATE plugin_jobs
+            SET status = 'running', attempts = attempts + 1, started_at = $1,
+                worker_id = $2, heartbeat_at = $1, lease_expires_at = $3
+            WHERE id = (
+                SELECT id FROM plugin_jobs
+                WHERE status = 'queued'
+                ORDER BY created_at
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING id, plugin_id, user_id, parameters, timeout_ms, status as "status: JobStatus", execution_id,
+                   attempts, error, created_at, updated_at, started_at, completed_at, worker_id, heartbeat_at,
+                   lease_expires_at, session_id, stdin_closed
+            "#,
+            now,
+            worker_id,
+            lease_expires_at
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn heartbeat_job(&self, id: Uuid, worker_id: &str, attempt: i32, lease: Duration) -> Result<bool, sqlx::Error> {
+        let now = Utc::now();
+        let lease_expires_at = now + chrono::Duration::milliseconds(lease.as_millis() as i64);
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE plugin_jobs
+            SET heartbeat_at = $4, lease_expires_at = $5
+            WHERE id = $1 AND worker_id = $2 AND attempts = $3 AND status = 'running'
+            "#,
+            id,
+            worker_id,
+            attempt,
+            now,
+            lease_expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_job_execution(&self, id: Uuid, worker_id: &str, attempt: i32, execution_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE plugin_jobs
+            SET execution_id = $4
+            WHERE id = $1 AND worker_id = $2 AND attempts = $3 AND status = 'running'
+            "#,
+            id,
+            worker_id,
+            attempt,
+            execution_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn finish_job(&self, id: Uuid, worker_id: &str, attempt: i32, status: JobStatus, execution_id: Option<Uuid>, error: Option<String>) -> Result<Option<PluginJob>, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        // A worker whose lease expired no longer owns the job; its result is discarded.
+        let job = sqlx::query_as!(
+            PluginJob,
+            r#"
+            UPDATE plugin_jobs
+            SET status = $4, execution_id = $5, error = $6, completed_at = $7, lease_expires_at = NULL
+            WHERE id = $1 AND worker_id = $2 AND attempts = $3 AND status = 'running'
+            RETURNING id, plugin_id, user_id, parameters, timeout_ms, status as "status: JobStatus", execution_id,
+                   attempts, error, created_at, updated_at, started_at, completed_at, worker_id, heartbeat_at,
+                   lease_expires_at, session_id, stdin_closed
+            "#,
+            id,
+            worker_id,
+            attempt,
+            status as JobStatus,
+            execution_id,
+            error,
+            now
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if job.is_some() {
+            sqlx::query!("SELECT pg_notify($1, $2)", JOB_FINISHED_CHANNEL, id.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(job)
+    }
+
+    async fn requeue_expired_jobs(&self, max_attempts: i32) -> Result<u64, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        // The worker that lost the lease stops its run and its outcome is discarded, so the
+        // execution it started would otherwise stay "running" forever.
+        let orphaned = sqlx::query!(
+            r#"
+            UPDATE plugin_executions
+            SET status = 'failed', error = 'Worker lease expired', completed_at = $1,
+                execution_time_ms = (EXTRACT(EPOCH FROM ($1 - started_at)) * 1000)::BIGINT
+            WHERE status = 'running' AND id IN (
+                SELECT execution_id FROM plugin_jobs
+                WHERE status = 'running' AND lease_expires_at < $1 AND execution_id IS NOT NULL
+            )
+            RETURNING plugin_id, execution_time_ms
+            "#,
+            now
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // Counted like any other finished execution, as `finish_execution` does.
+        for execution in &orphaned {
+            sqlx::query!(
+                r#"
+                UPDATE plugins
+                SET execution_count = execution_count + 1,
+                    average_execution_time_ms = (COALESCE(average_execution_time_ms, 0) * execution_count + $2) / (execution_count + 1),
+                    last_executed_at = $3
+                WHERE id = $1
+                "#,
+                execution.plugin_id,
+                execution.execution_time_ms,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let failed = sqlx::query_scalar!(
+            r#"
+            UPDATE plugin_jobs
+            SET status = 'failed', error = 'Worker lease expired too many times', completed_at = $1,
+                lease_expires_at = NULL
+            WHERE status = 'running' AND lease_expires_at < $1 AND attempts >= $2
+            RETURNING id
+            "#,
+            now,
+            max_attempts
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for id in &failed {
+            sqlx::query!("SELECT pg_notify($1, $2)", JOB_FINISHED_CHANNEL, id.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let requeued = sqlx::query!(
+            r#"
+            UPDATE plugin_jobs
+            SET status = 'queued', worker_id = NULL, started_at = NULL, lease_expires_at = NULL,
+                execution_id = NULL
+            WHERE status = 'running' AND lease_expires_at < $1
+            "#,
+            now
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if requeued > 0 {
+            sqlx::query!("SELECT pg_notify($1, $2)", JOB_QUEUED_CHANNEL, "")
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(requeued)
+    }
+
+    async fn write_job_input(&self, id: Uuid, data: Option<Vec<u8>>, max_pending: i64) -> Result<JobInputOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locking the job orders concurrent writers and keeps the pending count accurate.
+        let job = sqlx::query!(
+            r#"SELECT status as "status: JobStatus", stdin_closed FROM plugin_jobs WHERE id = $1 FOR UPDATE"#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match job {
+            Some(job) if !job.status.is_finished() && !job.stdin_closed => {}
+            _ => return Ok(JobInputOutcome::Closed),
+        }
+
+        let eof = data.is_none();
+        if !eof {
+            let pending = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) as "count!" FROM plugin_job_input WHERE job_id = $1"#,
+                id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if pending >= max_pending {
+                return Ok(JobInputOutcome::Full);
+            }
+        }
+
+        sqlx::query!(
+            "INSERT INTO plugin_job_input (job_id, data, eof) VALUES ($1, $2, $3)",
+            id,
+            data.unwrap_or_default(),
+            eof
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if eof {
+            sqlx::query!("UPDATE plugin_jobs SET stdin_closed = true WHERE id = $1", id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query!("SELECT pg_notify($1, $2)", JOB_INPUT_CHANNEL, id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(JobInputOutcome::Accepted)
+    }
+
+    async fn pending_job_input(&self, id: Uuid, limit: i64) -> Result<Vec<JobInput>, sqlx::Error> {
+        sqlx::query_as!(
+            JobInput,
+            "SELECT id, data, eof FROM plugin_job_input WHERE job_id = $1 ORDER BY id LIMIT $2",
+            id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn delete_job_input(&self, id: Uuid, up_to: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM plugin_job_input WHERE job_id = $1 AND id <= $2",
+            id,
+            up_to
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub family_id: Uuid,
+    pub username: String,
+    pub token_hash: String,
+    pub access_jti: Uuid,
+    pub access_expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub replaced_by: Option<Uuid>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewRefreshToken {
+    pub family_id: Uuid,
+    pub username: String,
+    pub token_hash: String,
+    pub access_jti: Uuid,
+    pub access_expires_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub username: String,
+    pub name: String,
+    pub key_prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > Utc::now())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub username: String,
+    pub name: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait::async_trait]
+pub trait TokenRepository {
+    async fn create_refresh_token(&self, token: NewRefreshToken) -> Result<RefreshToken, sqlx::Error>;
+    async fn get_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, sqlx::Error>;
+    // Returns `None` if `old_id` was already revoked, i.e. another request rotated it first.
+    async fn rotate_refresh_token(&self, old_id: Uuid, next: NewRefreshToken) -> Result<Option<RefreshToken>, sqlx::Error>;
+    async fn revoke_refresh_family(&self, family_id: Uuid) -> Result<u64, sqlx::Error>;
+    // Revokes every refresh token and live access token of the user; API keys are left alone.
+    async fn revoke_user_tokens(&self, username: &str) -> Result<u64, sqlx::Error>;
+    async fn revoke_user_api_keys(&self, username: &str) -> Result<u64, sqlx::Error>;
+    async fn revoke_access_token(&self, jti: Uuid, username: &str, expires_at: DateTime<Utc>) -> Result<(), sqlx::Error>;
+    async fn is_access_token_revoked(&self, jti: Uuid) -> Result<bool, sqlx::Error>;
+    async fn delete_expired_tokens(&self) -> Result<u64, sqlx::Error>;
+    async fn create_api_key(&self, key: NewApiKey) -> Result<ApiKey, sqlx::Error>;
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, sqlx::Error>;
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error>;
+    async fn revoke_api_key(&self, id: Uuid, username: &str) -> Result<bool, sqlx::Error>;
+    async fn touch_api_key(&self, id: Uuid) -> Result<(), sqlx::Error>;
+}
+
+pub struct PostgresTokenRepository {
+    pool: PgPool,
+}
+
+impl PostgresTokenRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenRepository for PostgresTokenRepository {
+    async fn create_refresh_token(&self, token: NewRefreshToken) -> Result<RefreshToken, sqlx::Error> {
+        sqlx::query_as!(
+            RefreshToken,
+            r#"
+            INSERT INTO refresh_tokens (family_id, username, token_hash, access_jti, access_expires_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+            token.family_id,
+            token.username,
+            token.token_hash,
+            token.access_jti,
+            token.access_expires_at,
+            token.expires_at
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn get_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, sqlx::Error> {
+        sqlx::query_as!(
+            RefreshToken,
+            "SELECT * FROM refresh_tokens WHERE token_hash = $1",
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn rotate_refresh_token(&self, old_id: Uuid, next: NewRefreshToken) -> Result<Option<RefreshToken>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let next = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            INSERT INTO refresh_tokens (family_id, username, token_hash, access_jti, access_expires_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+            next.family_id,
+            next.username,
+            next.token_hash,
+            next.access_jti,
+            next.access_expires_at,
+            next.expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let rotated = sqlx::query!(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW(), replaced_by = $2
+            WHERE id = $1 AND revoked_at IS NULL
+            "#,
+            old_id,
+            next.id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if rotated == 0 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+
+        tx.commit().await?;
+        Ok(Some(next))
+    }
+
+    async fn revoke_refresh_family(&self, family_id: Uuid) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens (jti, username, expires_at)
+            SELECT access_jti, username, access_expires_at
+            FROM refresh_tokens
+            WHERE family_id = $1 AND access_expires_at > NOW()
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            family_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let revoked = sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL",
+            family_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn revoke_user_tokens(&self, username: &str) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens (jti, username, expires_at)
+            SELECT access_jti, username, access_expires_at
+            FROM refresh_tokens
+            WHERE username = $1 AND access_expires_at > NOW()
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let revoked = sqlx::query!(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE username = $1 AND revoked_at IS NULL",
+            username
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn revoke_user_api_keys(&self, username: &str) -> Result<u64, sqlx::Error> {
+        let revoked = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE username = $1 AND revoked_at IS NULL",
+            username
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(revoked)
+    }
+
+    async fn revoke_access_token(&self, jti: Uuid, username: &str, expires_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens (jti, username, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            jti,
+            username,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_access_token_revoked(&self, jti: Uuid) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $1) AS "revoked!""#,
+            jti
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(revoked)
+    }
+
+    async fn delete_expired_tokens(&self) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let revoked = sqlx::query!("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        let refresh = sqlx::query!("DELETE FROM refresh_tokens WHERE expires_at < NOW()")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        tx.commit().await?;
+        Ok(revoked + refresh)
+    }
+
+    async fn create_api_key(&self, key: NewApiKey) -> Result<ApiKey, sqlx::Error> {
+        sqlx::query_as!(
+            ApiKey,
+            r#"
+            INSERT INTO api_keys (username, name, key_prefix, key_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+            key.username,
+            key.name,
+            key.key_prefix,
+            key.key_hash,
+            &key.scopes,
+            key.expires_at
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiKey,
+            "SELECT * FROM api_keys WHERE username = $1 ORDER BY created_at DESC",
+            username
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiKey,
+            "SELECT * FROM api_keys WHERE key_hash = $1",
+            key_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn revoke_api_key(&self, id: Uuid, username: &str) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND username = $2 AND revoked_at IS NULL",
+            id,
+            username
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(revoked > 0)
+    }
+
+    async fn touch_api_key(&self, id: Uuid) -> Result<(), sqlx::Error> {
+        // Recorded at most once a minute so busy CI keys do not write on every request.
+        sqlx::query!(
+            r#"
+            UPDATE api_keys SET last_used_at = NOW()
+            WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+            "#,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execution_cursor_round_trips() {
+        let cursor = ExecutionCursor {
+            started_at: DateTime::from_timestamp_micros(1_704_067_200_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        let decoded = ExecutionCursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.started_at, cursor.started_at);
+        assert_eq!(decoded.id, cursor.id);
+    }
+
+    #[test]
+    fn execution_cursor_keeps_microseconds() {
+        let cursor = ExecutionCursor {
+            started_at: DateTime::from_timestamp_micros(1).unwrap(),
+            id: Uuid::nil(),
+        };
+
+        assert_eq!(cursor.encode(), format!("1_{}", Uuid::nil()));
+    }
+
+    #[test]
+    fn execution_cursor_rejects_malformed_input() {
+        let id = Uuid::new_v4();
+        assert!(ExecutionCursor::decode("").is_none());
+        assert!(ExecutionCursor::decode("1704067200123456").is_none());
+        assert!(ExecutionCursor::decode(&format!("yesterday_{}", id)).is_none());
+        assert!(ExecutionCursor::decode("1704067200123456_not-a-uuid").is_none());
+        assert!(ExecutionCursor::decode(&format!("{}_{}", i64::MAX, id)).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::database::{LogStream, NewExecutionLogLine, PluginRepository};
+use crate::plugin::OutputHandler;
+
+#[derive(Debug, Clone)]
+pub struct ExecutionLogConfig {
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    // Log lines older than this are deleted; `None` keeps them forever.
+    pub retention: Option<Duration>,
+}
+
+impl Default for ExecutionLogConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            flush_interval: Duration::from_millis(500),
+            retention: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        }
+    }
+}
+
+/// Collects the output lines of one execution and writes them to the database in batches.
+pub struct ExecutionLogger {
+    handler: OutputHandler,
+    writer: JoinHandle<()>,
+}
+
+impl ExecutionLogger {
+    pub fn start(
+        repo: Arc<dyn PluginRepository + Send + Sync>,
+        execution_id: Uuid,
+        config: &ExecutionLogConfig,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let seq = AtomicI64::new(0);
+
+        // Called from the blocking execution thread; the unbounded send never waits.
+        let handler: OutputHandler = Arc::new(move |stream: LogStream, line: &str| {
+            let _ = tx.send(NewExecutionLogLine {
+                execution_id,
+                seq: seq.fetch_add(1, Ordering::Relaxed),
+                stream,
+                logged_at: Utc::now(),
+                line: line.to_string(),
+            });
+        });
+
+        Self {
+            handler,
+            writer: tokio::spawn(write_batches(repo, rx, config.clone())),
+        }
+    }
+
+    pub fn handler(&self) -> OutputHandler {
+        self.handler.clone()
+    }
+
+    /// Waits until every line produced so far has been written.
+    pub async fn finish(self) {
+        drop(self.handler);
+        let _ = self.writer.await;
+    }
+}
+
+async fn write_batches(
+    repo: Arc<dyn PluginRepository + Send + Sync>,
+    mut rx: mpsc::UnboundedReceiver<NewExecutionLogLine>,
+    config: ExecutionLogConfig,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    let mut ticker = tokio::time::interval(config.flush_interval);
+
+    loop {
+        tokio::select! {
+            line = rx.recv() => match line {
+                Some(line) => {
+                    batch.push(line);
+                    if batch.len() >= config.batch_size {
+                        flush(&repo, &mut batch).await;
+                    }
+                }
+                None => break,
+            },
+            _ = ticker.tick() => {
+                if !batch.is_empty() {
+                    flush(&repo, &mut batch).await;
+                }
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        flush(&repo, &mut batch).await;
+    }
+}
+
+async fn flush(repo: &Arc<dyn PluginRepository + Send + Sync>, batch: &mut Vec<NewExecutionLogLine>) {
+    let lines = std::mem::take(batch);
+    let count = lines.len();
+    if let Err(e) = repo.append_execution_logs(lines).await {
+        eprintln!("Failed to write {} execution log lines: {}", count, e);
+    }
+}
+
+pub fn start_retention(repo: Arc<dyn PluginRepository + Send + Sync>, config: &ExecutionLogConfig) {
+    let Some(retention) = config.retention else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let cutoff = Utc::now() - chrono::Duration::seconds(retention.as_secs() as i64);
+            match repo.delete_execution_logs_before(cutoff).await {
+                Ok(0) => {}
+                Ok(count) => println!("Deleted {} expired execution log lines", count),
+                Err(e) => eprintln!("Failed to delete expired execution logs: {}", e),
+            }
+        }
+    });
+}
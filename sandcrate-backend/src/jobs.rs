@@ -0,0 +1,414 @@
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use uuid::Uuid;
+
+use crate::database::{
+    CreateJobRequest, EnqueueOutcome, JobInputOutcome, JobRepository, JobStatus, PluginJob, JOB_FINISHED_CHANNEL,
+    JOB_INPUT_CHANNEL, JOB_QUEUED_CHANNEL,
+};
+use crate::plugin::{self, CancelToken, PluginError};
+use crate::services::PluginService;
+
+#[derive(Debug, Clone)]
+pub struct JobQueueConfig {
+    pub workers: usize,
+    pub max_queue_depth: i64,
+    pub poll_interval: Duration,
+    pub lease_duration: Duration,
+    pub max_attempts: i32,
+    pub worker_id: String,
+}
+
+impl Default for JobQueueConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            max_queue_depth: 1000,
+            poll_interval: Duration::from_millis(1000),
+            lease_duration: Duration::from_secs(30),
+            max_attempts: 3,
+            worker_id: default_worker_id(),
+        }
+    }
+}
+
+// Stdin chunks a session may queue for its plugin before further input is refused.
+const MAX_PENDING_INPUT: i64 = 16;
+
+// How soon input refused by a plugin that is not reading is offered to it again.
+const STDIN_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+// Unique per process, so a restarted worker never mistakes the old process's leases for its own.
+fn default_worker_id() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+    format!("{}-{}", host, Uuid::new_v4())
+}
+
+#[derive(Debug)]
+pub enum JobQueueError {
+    QueueFull(i64),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for JobQueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobQueueError::QueueFull(depth) => write!(f, "Job queue is full ({} jobs queued)", depth),
+            JobQueueError::Database(e) => write!(f, "Job queue database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JobQueueError {}
+
+impl From<sqlx::Error> for JobQueueError {
+    fn from(e: sqlx::Error) -> Self {
+        JobQueueError::Database(e)
+    }
+}
+
+pub struct JobQueue {
+    repo: Arc<dyn JobRepository + Send + Sync>,
+    config: JobQueueConfig,
+    queued: Notify,
+    finished_tx: broadcast::Sender<Uuid>,
+    input_tx: broadcast::Sender<Uuid>,
+}
+
+impl JobQueue {
+    pub fn new(repo: Arc<dyn JobRepository + Send + Sync>, config: JobQueueConfig) -> Self {
+        let (finished_tx, _) = broadcast::channel(256);
+        let (input_tx, _) = broadcast::channel(256);
+        Self {
+            repo,
+            config,
+            queued: Notify::new(),
+            finished_tx,
+            input_tx,
+        }
+    }
+
+    pub fn config(&self) -> &JobQueueConfig {
+        &self.config
+    }
+
+    pub async fn enqueue(&self, request: CreateJobRequest) -> Result<PluginJob, JobQueueError> {
+        match self.repo.enqueue_job(request, self.config.max_queue_depth).await? {
+            EnqueueOutcome::Queued(job) => {
+                self.queued.notify_one();
+                Ok(*job)
+            }
+            EnqueueOutcome::Full(depth) => Err(JobQueueError::QueueFull(depth)),
+        }
+    }
+
+    pub async fn get_job(&self, id: Uuid) -> Result<Option<PluginJob>, sqlx::Error> {
+        self.repo.get_job(id).await
+    }
+
+    /// Yields the id of every job that finished, in any process.
+    pub fn subscribe_finished(&self) -> broadcast::Receiver<Uuid> {
+        self.finished_tx.subscribe()
+    }
+
+    /// Queues stdin for the plugin of a session job, wherever it runs; `None` closes stdin.
+    pub async fn write_input(&self, id: Uuid, data: Option<Vec<u8>>) -> Result<JobInputOutcome, sqlx::Error> {
+        self.repo.write_job_input(id, data, MAX_PENDING_INPUT).await
+    }
+
+    /// Returns the job once it has finished, or its current state when `timeout` elapses first.
+    pub async fn wait_for_job(&self, id: Uuid, timeout: Duration) -> Result<Option<PluginJob>, sqlx::Error> {
+        // Subscribe before reading the row so a job finishing in between is not missed.
+        let mut finished_rx = self.finished_tx.subscribe();
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let job = self.repo.get_job(id).await?;
+            match &job {
+                Some(current) if !current.status.is_finished() => {}
+                _ => return Ok(job),
+            }
+
+            loop {
+                match tokio::time::timeout_at(deadline, finished_rx.recv()).await {
+                    Err(_) => return Ok(job),
+                    Ok(Ok(finished)) if finished != id => continue,
+                    Ok(_) => break,
+                }
+            }
+        }
+    }
+
+    /// Periodically returns jobs whose worker stopped sending heartbeats to the queue.
+    pub fn start_lease_reaper(self: &Arc<Self>) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(queue.config.lease_duration / 2);
+            loop {
+                interval.tick().await;
+                match queue.repo.requeue_expired_jobs(queue.config.max_attempts).await {
+                    Ok(0) => {}
+                    Ok(count) => println!("Requeued {} jobs with expired leases", count),
+                    Err(e) => eprintln!("Failed to requeue expired jobs: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Forwards Postgres notifications so that workers and waiters in this
+    /// process react to jobs queued or finished by any other process.
+    pub fn start_listener(self: &Arc<Self>, pool: PgPool) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = queue.listen(&pool).await {
+                    eprintln!("Job queue listener failed: {}", e);
+                }
+                tokio::time::sleep(queue.config.poll_interval).await;
+            }
+        });
+    }
+
+    async fn listen(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen_all([JOB_QUEUED_CHANNEL, JOB_FINISHED_CHANNEL, JOB_INPUT_CHANNEL]).await?;
+
+        loop {
+            let notification = listener.recv().await?;
+            if notification.channel() == JOB_QUEUED_CHANNEL {
+                self.queued.notify_one();
+            } else if let Ok(id) = Uuid::parse_str(notification.payload()) {
+                let tx = if notification.channel() == JOB_INPUT_CHANNEL { &self.input_tx } else { &self.finished_tx };
+                let _ = tx.send(id);
+            }
+        }
+    }
+
+    // Returns false once the job has been requeued for another worker.
+    async fn heartbeat(&self, job: &PluginJob) -> bool {
+        match self.repo.heartbeat_job(job.id, &self.config.worker_id, job.attempts, self.config.lease_duration).await {
+            Ok(true) => true,
+            Ok(false) => {
+                eprintln!("Worker {} lost the lease on job {}", self.config.worker_id, job.id);
+                false
+            }
+            Err(e) => {
+                eprintln!("Failed to send heartbeat for job {}: {}", job.id, e);
+                true
+            }
+        }
+    }
+
+    // Recorded as soon as the execution exists so clients can cancel it or follow its logs.
+    async fn attach_execution(&self, job: &PluginJob, execution_id: Uuid) {
+        if let Err(e) = self.repo.set_job_execution(job.id, &self.config.worker_id, job.attempts, execution_id).await {
+            eprintln!("Failed to record execution {} of job {}: {}", execution_id, job.id, e);
+        }
+    }
+
+    async fn finish_job(&self, job: &PluginJob, status: JobStatus, execution_id: Option<Uuid>, error: Option<String>) {
+        let id = job.id;
+        match self.repo.finish_job(id, &self.config.worker_id, job.attempts, status, execution_id, error).await {
+            Ok(Some(_)) => {
+                let _ = self.finished_tx.send(id);
+            }
+            Ok(None) => eprintln!("Discarding result of job {}: lease held by another worker", id),
+            Err(e) => eprintln!("Failed to record result of job {}: {}", id, e),
+        }
+    }
+}
+
+pub fn spawn_workers(queue: Arc<JobQueue>, plugin_service: Arc<PluginService>) {
+    for worker in 0..queue.config.workers {
+        tokio::spawn(worker_loop(worker, queue.clone(), plugin_service.clone()));
+    }
+}
+
+async fn worker_loop(worker: usize, queue: Arc<JobQueue>, plugin_service: Arc<PluginService>) {
+    loop {
+        match queue.repo.claim_next_job(&queue.config.worker_id, queue.config.lease_duration).await {
+            Ok(Some(job)) => {
+                let lease_lost = CancelToken::default();
+                let run = run_job(&queue, &plugin_service, &job, lease_lost.clone());
+                tokio::pin!(run);
+
+                let mut heartbeat = tokio::time::interval(queue.config.lease_duration / 3);
+                heartbeat.tick().await;
+                let (status, execution_id, error) = loop {
+                    tokio::select! {
+                        outcome = &mut run => break outcome,
+                        _ = heartbeat.tick(), if !lease_lost.is_cancelled() => {
+                            // The retry runs the plugin again, so this run must not keep going.
+                            if !queue.heartbeat(&job).await {
+                                lease_lost.cancel();
+                            }
+                        }
+                    }
+                };
+
+                queue.finish_job(&job, status, execution_id, error).await;
+            }
+            Ok(None) => {
+                // Fall back to polling in case a notification was missed while the listener reconnected.
+                tokio::select! {
+                    _ = queue.queued.notified() => {}
+                    _ = tokio::time::sleep(queue.config.poll_interval) => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("Job worker {} failed to claim a job: {}", worker, e);
+                tokio::time::sleep(queue.config.poll_interval).await;
+            }
+        }
+    }
+}
+
+pub async fn run_job(
+    queue: &JobQueue,
+    plugin_service: &PluginService,
+    job: &PluginJob,
+    cancel: CancelToken,
+) -> (JobStatus, Option<Uuid>, Option<String>) {
+    let plugin = match plugin_service.get_plugin_by_id(job.plugin_id).await {
+        Ok(Some(plugin)) => plugin,
+        Ok(None) => return (JobStatus::Failed, None, Some(format!("Plugin '{}' not found", job.plugin_id))),
+        Err(e) => return (JobStatus::Failed, None, Some(format!("Failed to load plugin: {}", e))),
+    };
+
+    if cancel.is_cancelled() {
+        return (JobStatus::Cancelled, None, Some("Worker lost the lease before the run started".to_string()));
+    }
+
+    let execution = match plugin_service
+        .record_execution_start(plugin.id, job.user_id, job.session_id.clone(), job.parameters.clone())
+        .await
+    {
+        Ok(execution) => execution,
+        Err(e) => return (JobStatus::Failed, None, Some(format!("Failed to record execution: {}", e))),
+    };
+    queue.attach_execution(job, execution.id).await;
+
+    // Only session jobs get a stdin pipe; a plugin reading stdin in any other job sees EOF at once.
+    let (stdin, feeder) = match job.session_id {
+        Some(_) => {
+            let (handle, pipe) = plugin::stdin_pipe();
+            let feeder = tokio::spawn(feed_stdin(
+                queue.repo.clone(),
+                queue.input_tx.subscribe(),
+                queue.config.poll_interval,
+                job.id,
+                handle,
+            ));
+            (Some(pipe), Some(feeder))
+        }
+        None => (None, None),
+    };
+
+    let logger = plugin_service.execution_logger(execution.id);
+    let options = plugin::ExecutionOptions {
+        parameters: job.parameters.clone(),
+        timeout: job.timeout_ms.map(|t| t as u64),
+        limits: plugin::ResourceLimits::for_plugin(&plugin),
+        cancel: plugin_service.track_execution(execution.id, cancel),
+        on_output: Some(logger.handler()),
+        stdin,
+    };
+    let file_path = plugin.file_path.clone();
+
+    let start_time = std::time::Instant::now();
+    let result = tokio::task::spawn_blocking(move || plugin::run_plugin_with_options(&file_path, options))
+        .await
+        .unwrap_or_else(|e| Err(PluginError::Runtime(e.to_string()).into()));
+    let execution_time_ms = start_time.elapsed().as_millis() as i64;
+    if let Some(feeder) = feeder {
+        feeder.abort();
+    }
+    logger.finish().await;
+
+    if let Err(e) = plugin_service
+        .record_execution_outcome(execution.id, &result, execution_time_ms)
+        .await
+    {
+        eprintln!("Failed to record outcome of execution {}: {}", execution.id, e);
+    }
+
+    match result {
+        Ok(output) if output.success() => (JobStatus::Completed, Some(execution.id), None),
+        Ok(output) => (
+            JobStatus::Failed,
+            Some(execution.id),
+            Some(format!("Plugin exited with code {}", output.exit_code)),
+        ),
+        Err(failed) => match failed.error {
+            PluginError::Cancelled => (JobStatus::Cancelled, Some(execution.id), Some(failed.error.to_string())),
+            e => (JobStatus::Failed, Some(execution.id), Some(e.to_string())),
+        },
+    }
+}
+
+/// Hands the stdin a session wrote for its job to the plugin, oldest chunk first. Chunks stay
+/// queued in the database until the plugin's pipe takes them.
+async fn feed_stdin(
+    repo: Arc<dyn JobRepository + Send + Sync>,
+    mut input_rx: broadcast::Receiver<Uuid>,
+    poll_interval: Duration,
+    id: Uuid,
+    stdin: plugin::StdinHandle,
+) {
+    loop {
+        let mut backlogged = false;
+        match repo.pending_job_input(id, MAX_PENDING_INPUT).await {
+            Ok(chunks) => {
+                let mut delivered = None;
+                let mut closed = false;
+                for chunk in chunks {
+                    if chunk.eof {
+                        stdin.close();
+                        closed = true;
+                    } else {
+                        match stdin.write(chunk.data) {
+                            Ok(()) => {}
+                            Err(plugin::StdinError::Full) => {
+                                backlogged = true;
+                                break;
+                            }
+                            Err(plugin::StdinError::Closed) => closed = true,
+                        }
+                    }
+                    delivered = Some(chunk.id);
+                    if closed {
+                        break;
+                    }
+                }
+                if let Some(up_to) = delivered {
+                    if let Err(e) = repo.delete_job_input(id, up_to).await {
+                        eprintln!("Failed to remove delivered stdin of job {}: {}", id, e);
+                    }
+                }
+                if closed {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("Failed to read stdin of job {}: {}", id, e),
+        }
+
+        if backlogged {
+            tokio::time::sleep(STDIN_RETRY_INTERVAL).await;
+            continue;
+        }
+        // Polling covers notifications missed while the listener reconnected.
+        let _ = tokio::time::timeout(poll_interval, async {
+            loop {
+                match input_rx.recv().await {
+                    Ok(job) if job != id => continue,
+                    _ => break,
+                }
+            }
+        })
+        .await;
+    }
+}
@@ -1,38 +1,99 @@
 mod api;
 mod auth;
+mod auth_provider;
+mod config;
 pub mod plugin;
+mod module_cache;
 mod websocket;
+mod ws_protocol;
 mod database;
 mod services;
+mod jobs;
+mod execution_logs;
 
-use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tower_http::cors::CorsLayer;
 use axum::{Router, routing::get};
 
 pub use plugin::run_plugin;
-pub use websocket::{WebSocketManager, PluginExecutionSession};
-pub use database::{DatabaseConfig, create_pool, PostgresPluginRepository, PluginRepository};
+pub use config::{ConfigError, ServerConfig};
+pub use jobs::{JobQueue, JobQueueConfig};
+pub use websocket::{WebSocketConfig, WebSocketManager, PluginExecutionSession, SessionSink};
+pub use database::{
+    DatabaseConfig, create_pool, PostgresJobRepository, PostgresPluginRepository, PostgresTokenRepository, PostgresUserRepository, PluginRepository,
+};
 pub use services::PluginService;
+use database::UserRepository;
+
+fn load_config() -> ServerConfig {
+    dotenv::dotenv().ok();
+    
+    match ServerConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn init_plugin_runtime(config: &ServerConfig) {
+    if let Err(e) = plugin::init_runtime(config.runtime.clone()) {
+        eprintln!("Failed to initialize the plugin runtime: {}", e);
+        std::process::exit(1);
+    }
+}
 
 #[tokio::main]
 pub async fn run_backend() {
-    dotenv::dotenv().ok();
+    let config = load_config();
+    init_plugin_runtime(&config);
+    
+    let db_pool = create_pool(&config.database).await.expect("Failed to create database pool");
+    let token_repo = Arc::new(PostgresTokenRepository::new(db_pool.clone()));
+    let user_repo = Arc::new(PostgresUserRepository::new(db_pool.clone()));
+    let auth_config = match config.auth_config(token_repo, user_repo) {
+        Ok(auth_config) => Arc::new(auth_config),
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if config.dev_mode {
+        eprintln!("WARNING: running in development mode; do not use this configuration in production");
+    }
+    
+    if let Err(e) = std::fs::create_dir_all(&config.plugins_dir) {
+        eprintln!("Failed to create plugins directory {}: {}", config.plugins_dir.display(), e);
+    }
     
-    let db_config = DatabaseConfig::default();
-    let db_pool = create_pool(&db_config).await.expect("Failed to create database pool");
     let plugin_repo = Arc::new(PostgresPluginRepository::new(db_pool.clone()));
-    let plugin_service = Arc::new(PluginService::new(plugin_repo.clone()));
+    let plugin_service = Arc::new(PluginService::new(plugin_repo.clone(), &config));
+    
+    let ws_manager = Arc::new(websocket::WebSocketManager::new(config.websocket.clone()));
+    
+    match plugin_service.sync_plugins_from_filesystem().await {
+        Ok(plugins) if !plugins.is_empty() => println!("Imported {} plugins from filesystem", plugins.len()),
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to sync plugins from filesystem: {}", e),
+    }
+    
+    let job_repo = Arc::new(PostgresJobRepository::new(db_pool.clone()));
+    let job_queue = Arc::new(jobs::JobQueue::new(job_repo, config.jobs.clone()));
     
-    let auth_config = Arc::new(auth::AuthConfig::new());
-    let ws_manager = Arc::new(websocket::WebSocketManager::new());
+    auth::start_token_cleanup(auth_config.clone());
+    plugin_service.start_listener(db_pool.clone());
+    plugin_service.start_log_retention();
+    job_queue.start_listener(db_pool.clone());
+    job_queue.start_lease_reaper();
+    // Plugins only run on `sandcrate-worker` processes; the API queues jobs and relays their output.
     
-    let api_router = api::routes().with_state((auth_config.clone(), plugin_service.clone()));
+    let api_router = api::routes(config.max_plugin_size_bytes).with_state((auth_config.clone(), plugin_service.clone(), job_queue.clone()));
     let auth_router = auth::auth_routes().with_state(auth_config.clone());
     let ws_router = Router::new()
         .route("/plugins", get(websocket::plugin_execution_websocket))
-        .with_state((auth_config, ws_manager));
+        .with_state((auth_config, ws_manager, plugin_service, job_queue));
     
     let app = Router::new()
         .nest("/api", api_router)
@@ -40,10 +101,126 @@ pub async fn run_backend() {
         .nest("/ws", ws_router)
         .layer(CorsLayer::permissive());
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let addr = config.bind_addr();
+    println!("Listening on {}", addr);
 
     let listener = TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app.into_make_service())
         .await
         .unwrap();
 }
+
+#[tokio::main]
+pub async fn run_worker() {
+    // Workers never issue or check tokens, so they do not need a JWT secret.
+    let config = load_config();
+    init_plugin_runtime(&config);
+    
+    let db_pool = create_pool(&config.database).await.expect("Failed to create database pool");
+    let plugin_repo = Arc::new(PostgresPluginRepository::new(db_pool.clone()));
+    let plugin_service = Arc::new(PluginService::new(plugin_repo, &config));
+    
+    let job_repo = Arc::new(PostgresJobRepository::new(db_pool.clone()));
+    let job_queue = Arc::new(jobs::JobQueue::new(job_repo, config.jobs.clone()));
+    
+    let queue_config = job_queue.config();
+    println!("Worker {} starting {} executors", queue_config.worker_id, queue_config.workers);
+    
+    plugin_service.start_listener(db_pool.clone());
+    job_queue.start_listener(db_pool);
+    job_queue.start_lease_reaper();
+    jobs::spawn_workers(job_queue.clone(), plugin_service);
+    
+    tokio::signal::ctrl_c().await.expect("Failed to listen for shutdown signal");
+    println!("Worker {} shutting down", job_queue.config().worker_id);
+}
+
+// Creates a database-provider account or resets its password and, if given, its role, e.g. the first
+// admin of a deployment without local users: `sandcrate-user <username> [admin|user|guest]`, password
+// on stdin.
+#[tokio::main]
+pub async fn run_create_user() {
+    let config = load_config();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (username, role) = match args.as_slice() {
+        [username] => (username.clone(), None),
+        [username, role] => match role.parse::<database::UserRole>() {
+            Ok(role) => (username.clone(), Some(role)),
+            Err(e) => {
+                eprintln!("Invalid role: {}", e);
+                std::process::exit(2);
+            }
+        },
+        _ => {
+            eprintln!("Usage: sandcrate-user <username> [admin|user|guest] < password");
+            std::process::exit(2);
+        }
+    };
+
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        eprintln!("Failed to read password: {}", e);
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.chars().count() < auth_provider::MIN_PASSWORD_LEN {
+        eprintln!("Password must be at least {} characters long", auth_provider::MIN_PASSWORD_LEN);
+        std::process::exit(1);
+    }
+    let password_hash = auth_provider::hash_password(password).expect("Failed to hash password");
+
+    let db_pool = create_pool(&config.database).await.expect("Failed to create database pool");
+    let users = PostgresUserRepository::new(db_pool);
+    let existing = users.get_user_by_username(&username).await.unwrap_or_else(|e| {
+        eprintln!("Failed to load user {}: {}", username, e);
+        std::process::exit(1);
+    });
+    // The same rule as `PUT /auth/users/:username/role`: a deployment always keeps an admin.
+    if let (Some(user), Some(role)) = (&existing, role) {
+        if user.role == database::UserRole::Admin && role != user.role && user.is_active {
+            match users.count_active_admins().await {
+                Ok(admins) if admins > 1 => {}
+                Ok(_) => {
+                    eprintln!("Cannot make {} a {}: it is the last active admin", username, role.as_str());
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Failed to count admins: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let result = match existing {
+        Some(_) => update_user(&users, &username, role, &password_hash).await,
+        None => users
+            .create_user(&username, &username, role.unwrap_or(database::UserRole::User), Some(&password_hash))
+            .await
+            .map(|_| "Created"),
+    };
+
+    match result {
+        Ok(action) => println!("{} user {}", action, username),
+        Err(e) => {
+            eprintln!("Failed to save user {}: {}", username, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn update_user(
+    users: &PostgresUserRepository,
+    username: &str,
+    role: Option<database::UserRole>,
+    password_hash: &str,
+) -> Result<&'static str, sqlx::Error> {
+    users.set_password_hash(username, password_hash).await?;
+    match role {
+        Some(role) => {
+            users.set_user_role(username, role).await?;
+            Ok("Updated password and role of")
+        }
+        None => Ok("Updated password of"),
+    }
+}
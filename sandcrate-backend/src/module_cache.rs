@@ -0,0 +1,204 @@
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use wasmtime::{Engine, Module};
+
+#[derive(Debug, Clone)]
+pub struct ModuleCacheConfig {
+    pub capacity: usize,
+    // Precompiled modules are kept here across restarts; `None` keeps them in memory only.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for ModuleCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            cache_dir: None,
+        }
+    }
+}
+
+pub struct ModuleCache {
+    engine: Engine,
+    modules: Mutex<LruCache<String, Module>>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl ModuleCache {
+    pub fn new(engine: Engine, config: ModuleCacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.capacity).unwrap_or(NonZeroUsize::MIN);
+
+        if let Some(dir) = &config.cache_dir {
+            if let Err(e) = fs::create_dir_all(dir) {
+                eprintln!("Failed to create module cache directory {}: {}", dir.display(), e);
+            }
+        }
+
+        Self {
+            engine,
+            modules: Mutex::new(LruCache::new(capacity)),
+            cache_dir: config.cache_dir,
+        }
+    }
+
+    pub fn content_hash(wasm_bytes: &[u8]) -> String {
+        Sha256::digest(wasm_bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.lock().unwrap().len()
+    }
+
+    pub fn get_or_compile(&self, hash: &str, wasm_bytes: &[u8]) -> Result<Module, wasmtime::Error> {
+        if let Some(module) = self.modules.lock().unwrap().get(hash) {
+            return Ok(module.clone());
+        }
+
+        // Compile outside the lock so one slow module doesn't stall every other execution.
+        let module = match self.load_precompiled(hash) {
+            Some(module) => module,
+            None => {
+                let module = Module::new(&self.engine, wasm_bytes)?;
+                self.store_precompiled(hash, &module);
+                module
+            }
+        };
+
+        self.modules.lock().unwrap().put(hash.to_string(), module.clone());
+        Ok(module)
+    }
+
+    fn artifact_path(&self, hash: &str) -> Option<PathBuf> {
+        self.cache_dir.as_ref().map(|dir| dir.join(format!("{}.cwasm", hash)))
+    }
+
+    fn load_precompiled(&self, hash: &str) -> Option<Module> {
+        let path = self.artifact_path(hash)?;
+        if !path.exists() {
+            return None;
+        }
+
+        // SAFETY: artifacts in the cache directory are only ever written by
+        // `store_precompiled` with an engine of this process' configuration.
+        // Incompatible artifacts are rejected by wasmtime and recompiled.
+        match unsafe { Module::deserialize_file(&self.engine, &path) } {
+            Ok(module) => Some(module),
+            Err(e) => {
+                eprintln!("Discarding precompiled module {}: {}", path.display(), e);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    fn store_precompiled(&self, hash: &str, module: &Module) {
+        let Some(path) = self.artifact_path(hash) else {
+            return;
+        };
+
+        let bytes = match module.serialize() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Failed to serialize module {}: {}", hash, e);
+                return;
+            }
+        };
+
+        // Write to a temporary file first so a concurrent reader never sees a partial artifact.
+        let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        if let Err(e) = fs::write(&tmp_path, bytes).and_then(|_| fs::rename(&tmp_path, &path)) {
+            eprintln!("Failed to write precompiled module {}: {}", path.display(), e);
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &str = r#"(module (func (export "_start")))"#;
+    // Anything compiled after a hit would fail on these bytes.
+    const GARBAGE: &[u8] = b"not a module";
+
+    fn scratch_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("sandcrate-module-cache-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn content_hash_is_the_hex_sha256_of_the_bytes() {
+        assert_eq!(
+            ModuleCache::content_hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            ModuleCache::content_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn second_lookup_of_a_hash_is_served_from_memory() {
+        let cache = ModuleCache::new(Engine::default(), ModuleCacheConfig::default());
+        let hash = ModuleCache::content_hash(WAT.as_bytes());
+
+        cache.get_or_compile(&hash, WAT.as_bytes()).unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.get_or_compile(&hash, GARBAGE).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // A different hash misses and compiles its own bytes.
+        assert!(cache.get_or_compile(&ModuleCache::content_hash(GARBAGE), GARBAGE).is_err());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn least_recently_used_module_is_evicted_at_capacity() {
+        let cache = ModuleCache::new(Engine::default(), ModuleCacheConfig { capacity: 1, cache_dir: None });
+        let other = r#"(module (func (export "run")))"#;
+
+        cache.get_or_compile("first", WAT.as_bytes()).unwrap();
+        cache.get_or_compile("second", other.as_bytes()).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get_or_compile("first", GARBAGE).is_err());
+    }
+
+    #[test]
+    fn precompiled_artifacts_survive_a_new_cache() {
+        let dir = scratch_dir();
+        let engine = Engine::default();
+        let hash = ModuleCache::content_hash(WAT.as_bytes());
+        let config = ModuleCacheConfig { capacity: 4, cache_dir: Some(dir.clone()) };
+
+        ModuleCache::new(engine.clone(), config.clone()).get_or_compile(&hash, WAT.as_bytes()).unwrap();
+        assert!(dir.join(format!("{}.cwasm", hash)).exists());
+
+        // A restarted server loads the artifact instead of compiling the bytes it is given.
+        let restarted = ModuleCache::new(engine, config);
+        let module = restarted.get_or_compile(&hash, GARBAGE).unwrap();
+        assert!(module.get_export("_start").is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_artifacts_are_discarded_and_recompiled() {
+        let dir = scratch_dir();
+        let hash = ModuleCache::content_hash(WAT.as_bytes());
+        let cache = ModuleCache::new(Engine::default(), ModuleCacheConfig { capacity: 4, cache_dir: Some(dir.clone()) });
+        let artifact = dir.join(format!("{}.cwasm", hash));
+        fs::write(&artifact, GARBAGE).unwrap();
+
+        cache.get_or_compile(&hash, WAT.as_bytes()).unwrap();
+        assert_ne!(fs::read(&artifact).unwrap(), GARBAGE);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
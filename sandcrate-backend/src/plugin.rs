@@ -1,13 +1,609 @@
+use std::fmt;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use lru::LruCache;
+use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasi_common::I32Exit;
 use wasmtime::*;
-use wasmtime_wasi::WasiCtxBuilder;
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+use serde::Serialize;
 use serde_json::Value;
-use tokio::sync::broadcast;
 
-pub fn list_plugins() -> Vec<String> {
-    let plugins_dir = Path::new("../assets/plugins");
+use crate::database::{ExecutionStatus, LogStream, Plugin};
+use crate::module_cache::{ModuleCache, ModuleCacheConfig};
 
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+
+// Execution parameters are handed to the guest as JSON in this WASI environment variable.
+pub const PARAMETERS_ENV: &str = "SANDCRATE_PARAMETERS";
+
+// Each captured stream is cut off after this many bytes; its lines are still streamed in full.
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+// A longer line without a newline is streamed in pieces of this size.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum PluginError {
+    Timeout(u64),
+    MemoryLimitExceeded(usize),
+    TableLimitExceeded(u32),
+    NoEntryPoint,
+    Cancelled,
+    Runtime(String),
+}
+
+impl PluginError {
+    pub fn execution_status(&self) -> ExecutionStatus {
+        match self {
+            PluginError::Timeout(_) => ExecutionStatus::Timeout,
+            PluginError::Cancelled => ExecutionStatus::Cancelled,
+            _ => ExecutionStatus::Failed,
+        }
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::Timeout(ms) => write!(f, "Plugin execution timed out after {} ms", ms),
+            PluginError::MemoryLimitExceeded(limit) => write!(f, "memory limit exceeded: plugin may use at most {} bytes", limit),
+            PluginError::TableLimitExceeded(limit) => write!(f, "table limit exceeded: plugin may use at most {} table elements", limit),
+            PluginError::NoEntryPoint => write!(f, "No suitable entry function found in WASM module"),
+            PluginError::Cancelled => write!(f, "Plugin execution was cancelled"),
+            PluginError::Runtime(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<wasmtime::Error> for PluginError {
+    fn from(e: wasmtime::Error) -> Self {
+        PluginError::Runtime(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for PluginError {
+    fn from(e: std::io::Error) -> Self {
+        PluginError::Runtime(e.to_string())
+    }
+}
+
+/// A run that did not finish, with what the guest wrote and used before it was stopped.
+#[derive(Debug)]
+pub struct FailedRun {
+    pub error: PluginError,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+    pub peak_memory_bytes: u64,
+}
+
+impl From<PluginError> for FailedRun {
+    fn from(error: PluginError) -> Self {
+        Self {
+            error,
+            stdout: String::new(),
+            stderr: String::new(),
+            truncated: false,
+            peak_memory_bytes: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimits {
+    pub max_memory_bytes: usize,
+    pub max_table_elements: u32,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_memory_bytes: 256 * 1024 * 1024,
+            max_table_elements: 10_000,
+        }
+    }
+}
+
+impl ResourceLimits {
+    // Configured limits, overridden by whatever the plugin row sets explicitly.
+    pub fn for_plugin(plugin: &Plugin) -> Self {
+        Self::with_overrides(plugin.max_memory_bytes, plugin.max_table_elements)
+    }
+
+    pub fn with_overrides(max_memory_bytes: Option<i64>, max_table_elements: Option<i32>) -> Self {
+        let defaults = runtime().default_limits;
+        Self {
+            max_memory_bytes: max_memory_bytes
+                .map(|b| b as usize)
+                .unwrap_or(defaults.max_memory_bytes),
+            max_table_elements: max_table_elements
+                .map(|e| e as u32)
+                .unwrap_or(defaults.max_table_elements),
+        }
+    }
+}
+
+// Shared flag checked on every epoch tick; cancelling interrupts the guest within one tick.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Receives every complete output line of an execution, tagged with its stream.
+pub type OutputHandler = Arc<dyn Fn(LogStream, &str) + Send + Sync>;
+
+// Chunks of stdin buffered for a plugin that is not reading; writers are refused beyond this.
+const STDIN_QUEUE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinError {
+    // The plugin has not caught up with earlier input yet.
+    Full,
+    // Stdin was closed or the plugin has stopped reading.
+    Closed,
+}
+
+/// Writing end of a plugin's stdin; closing it (or dropping every handle) signals end of input.
+#[derive(Clone)]
+pub struct StdinHandle(Arc<Mutex<Option<mpsc::SyncSender<Vec<u8>>>>>);
+
+impl StdinHandle {
+    pub fn write(&self, data: Vec<u8>) -> Result<(), StdinError> {
+        match self.0.lock().unwrap().as_ref() {
+            Some(tx) => tx.try_send(data).map_err(|e| match e {
+                mpsc::TrySendError::Full(_) => StdinError::Full,
+                mpsc::TrySendError::Disconnected(_) => StdinError::Closed,
+            }),
+            None => Err(StdinError::Closed),
+        }
+    }
+
+    pub fn close(&self) {
+        self.0.lock().unwrap().take();
+    }
+}
+
+/// Reading end of a plugin's stdin, handed to the execution through `ExecutionOptions`.
+#[derive(Clone)]
+pub struct StdinPipe(Arc<Mutex<mpsc::Receiver<Vec<u8>>>>);
+
+pub fn stdin_pipe() -> (StdinHandle, StdinPipe) {
+    let (tx, rx) = mpsc::sync_channel(STDIN_QUEUE);
+    (
+        StdinHandle(Arc::new(Mutex::new(Some(tx)))),
+        StdinPipe(Arc::new(Mutex::new(rx))),
+    )
+}
+
+#[derive(Clone, Default)]
+pub struct ExecutionOptions {
+    pub parameters: Option<Value>,
+    pub timeout: Option<u64>,
+    pub limits: ResourceLimits,
+    pub cancel: CancelToken,
+    pub on_output: Option<OutputHandler>,
+    // Without a pipe the guest sees an empty stdin.
+    pub stdin: Option<StdinPipe>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExecutionOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+    pub truncated: bool,
+    pub peak_memory_bytes: u64,
+}
+
+struct PluginLimiter {
+    limits: ResourceLimits,
+    memory_bytes: usize,
+    peak_memory_bytes: usize,
+    violation: Option<PluginError>,
+}
+
+impl PluginLimiter {
+    fn new(limits: ResourceLimits) -> Self {
+        Self {
+            limits,
+            memory_bytes: 0,
+            peak_memory_bytes: 0,
+            violation: None,
+        }
+    }
+}
+
+impl ResourceLimiter for PluginLimiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        let total = self.memory_bytes - current + desired;
+        if total > self.limits.max_memory_bytes {
+            self.violation = Some(PluginError::MemoryLimitExceeded(self.limits.max_memory_bytes));
+            return Err(PluginError::MemoryLimitExceeded(self.limits.max_memory_bytes).into());
+        }
+        self.memory_bytes = total;
+        self.peak_memory_bytes = self.peak_memory_bytes.max(total);
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: u32, desired: u32, _maximum: Option<u32>) -> Result<bool> {
+        if desired > self.limits.max_table_elements {
+            self.violation = Some(PluginError::TableLimitExceeded(self.limits.max_table_elements));
+            return Err(PluginError::TableLimitExceeded(self.limits.max_table_elements).into());
+        }
+        Ok(true)
+    }
+}
+
+struct PluginState {
+    wasi: WasiCtx,
+    limiter: PluginLimiter,
+}
+
+impl ExecutionOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+type LineHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+struct CapturedBytes {
+    data: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+    pending: Vec<u8>,
+    on_line: Option<LineHandler>,
+}
+
+impl CapturedBytes {
+    fn emit_line(&self, line: &[u8]) {
+        if let Some(on_line) = &self.on_line {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end();
+            if !line.is_empty() {
+                on_line(line);
+            }
+        }
+    }
+}
+
+// Runs that trap never reach `contents()`; still deliver an unterminated last line.
+impl Drop for CapturedBytes {
+    fn drop(&mut self) {
+        let rest = std::mem::take(&mut self.pending);
+        self.emit_line(&rest);
+    }
+}
+
+#[derive(Clone)]
+struct OutputBuffer {
+    inner: Arc<Mutex<CapturedBytes>>,
+}
+
+impl OutputBuffer {
+    // Calls `on_line` for every complete line as soon as the guest writes it.
+    fn with_line_handler(limit: usize, on_line: Option<LineHandler>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CapturedBytes {
+                data: Vec::new(),
+                limit,
+                truncated: false,
+                pending: Vec::new(),
+                on_line,
+            })),
+        }
+    }
+
+    fn contents(&self) -> (String, bool) {
+        let mut inner = self.inner.lock().unwrap();
+        let rest = std::mem::take(&mut inner.pending);
+        inner.emit_line(&rest);
+        (String::from_utf8_lossy(&inner.data).into_owned(), inner.truncated)
+    }
+}
+
+fn stream_handler(on_output: &Option<OutputHandler>, stream: LogStream) -> Option<LineHandler> {
+    let on_output = on_output.clone()?;
+    Some(Arc::new(move |line: &str| on_output(stream, line)))
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let remaining = inner.limit.saturating_sub(inner.data.len());
+        if buf.len() > remaining {
+            inner.truncated = true;
+        }
+        let kept = buf.len().min(remaining);
+        inner.data.extend_from_slice(&buf[..kept]);
+        // The cap only bounds what is kept in memory; lines past it still reach the handler.
+        if inner.on_line.is_some() {
+            inner.pending.extend_from_slice(buf);
+            loop {
+                let end = match inner.pending.iter().position(|b| *b == b'\n') {
+                    Some(end) => end + 1,
+                    None if inner.pending.len() >= MAX_LINE_BYTES => MAX_LINE_BYTES,
+                    None => break,
+                };
+                let line: Vec<u8> = inner.pending.drain(..end).collect();
+                inner.emit_line(&line);
+            }
+        }
+        // Report everything as written so the guest does not fail once the cap is hit.
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A guest blocked in a stdin read is inside a host call where epoch interruption cannot reach it,
+// so the reader itself gives up (reporting EOF) once the execution is cancelled or out of time.
+struct StdinReader {
+    pipe: StdinPipe,
+    buffered: Vec<u8>,
+    cancel: CancelToken,
+    // `None` when the timeout is too large to represent; only cancellation ends the wait then.
+    deadline: Option<Instant>,
+}
+
+impl Read for StdinReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffered.is_empty() {
+            if self.cancel.is_cancelled() || self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(0);
+            }
+            match self.pipe.0.lock().unwrap().recv_timeout(Duration::from_millis(EPOCH_TICK_MS)) {
+                Ok(data) => self.buffered = data,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.buffered.len());
+        buf[..n].copy_from_slice(&self.buffered[..n]);
+        self.buffered.drain(..n);
+        Ok(n)
+    }
+}
+
+// How often the shared engine's epoch advances; timeouts are rounded up to a whole tick.
+const EPOCH_TICK_MS: u64 = 10;
+
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct PoolingConfig {
+    pub enabled: bool,
+    pub max_instances: u32,
+    pub memory_bytes_per_slot: usize,
+}
+
+impl Default for PoolingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_instances: 100,
+            memory_bytes_per_slot: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Settings of the shared plugin runtime, loaded with the rest of the `ServerConfig`.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeConfig {
+    // Applied to plugins that do not set their own limits.
+    pub limits: ResourceLimits,
+    pub pooling: PoolingConfig,
+    pub module_cache: ModuleCacheConfig,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeStats {
+    pub pooling_enabled: bool,
+    pub max_instances: Option<u32>,
+    pub memory_bytes_per_slot: Option<usize>,
+    pub active_instances: u64,
+    pub total_instantiations: u64,
+    pub cached_modules: usize,
+    pub cached_instance_pres: usize,
+    pub instance_pre_hits: u64,
+    pub instance_pre_misses: u64,
+}
+
+#[derive(Default)]
+struct RuntimeCounters {
+    active_instances: AtomicU64,
+    total_instantiations: AtomicU64,
+    instance_pre_hits: AtomicU64,
+    instance_pre_misses: AtomicU64,
+}
+
+// Keeps `active_instances` accurate however the execution ends.
+struct ActiveInstance<'a>(&'a AtomicU64);
+
+impl<'a> ActiveInstance<'a> {
+    fn enter(counter: &'a AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for ActiveInstance<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct PluginRuntime {
+    engine: Engine,
+    linker: Linker<PluginState>,
+    modules: ModuleCache,
+    pooling: PoolingConfig,
+    default_limits: ResourceLimits,
+    instance_pres: Mutex<LruCache<String, InstancePre<PluginState>>>,
+    counters: RuntimeCounters,
+}
+
+impl PluginRuntime {
+    fn new(config: RuntimeConfig) -> Result<Self, PluginError> {
+        let RuntimeConfig { limits, pooling, module_cache: cache_config } = config;
+        
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        
+        if pooling.enabled {
+            let mut pool = PoolingAllocationConfig::default();
+            pool.total_core_instances(pooling.max_instances)
+                .total_memories(pooling.max_instances)
+                .total_tables(pooling.max_instances)
+                .memory_pages((pooling.memory_bytes_per_slot / WASM_PAGE_SIZE) as u64)
+                .table_elements(limits.max_table_elements);
+            config.allocation_strategy(InstanceAllocationStrategy::Pooling(pool));
+        }
+        
+        let engine = Engine::new(&config)?;
+        
+        let ticker = engine.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(EPOCH_TICK_MS));
+            ticker.increment_epoch();
+        });
+        
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |state: &mut PluginState| &mut state.wasi)?;
+        
+        let capacity = NonZeroUsize::new(cache_config.capacity).unwrap_or(NonZeroUsize::MIN);
+        
+        Ok(Self {
+            modules: ModuleCache::new(engine.clone(), cache_config),
+            instance_pres: Mutex::new(LruCache::new(capacity)),
+            counters: RuntimeCounters::default(),
+            engine,
+            linker,
+            pooling,
+            default_limits: limits,
+        })
+    }
+    
+    /// Pooled instances get fixed-size slots, sized from the configured limits, so a plugin may
+    /// not be allowed more memory or table elements than a slot holds.
+    pub fn check_limits(&self, limits: &ResourceLimits) -> Result<(), String> {
+        if !self.pooling.enabled {
+            return Ok(());
+        }
+        if limits.max_memory_bytes > self.pooling.memory_bytes_per_slot {
+            return Err(format!(
+                "memory limit of {} bytes exceeds the {} bytes of a pool slot (PLUGIN_POOL_MEMORY_MB)",
+                limits.max_memory_bytes, self.pooling.memory_bytes_per_slot
+            ));
+        }
+        if limits.max_table_elements > self.default_limits.max_table_elements {
+            return Err(format!(
+                "table limit of {} elements exceeds the {} elements of a pool slot (PLUGIN_MAX_TABLE_ELEMENTS)",
+                limits.max_table_elements, self.default_limits.max_table_elements
+            ));
+        }
+        Ok(())
+    }
+    
+    fn load_module(&self, plugin_path: &str) -> Result<(String, Module), PluginError> {
+        let wasm_bytes = fs::read(plugin_path)?;
+        let hash = ModuleCache::content_hash(&wasm_bytes);
+        let module = self.modules.get_or_compile(&hash, &wasm_bytes)?;
+        Ok((hash, module))
+    }
+    
+    fn instantiate(
+        &self,
+        store: &mut Store<PluginState>,
+        hash: &str,
+        module: &Module,
+    ) -> Result<Instance, wasmtime::Error> {
+        self.counters.total_instantiations.fetch_add(1, Ordering::Relaxed);
+        
+        if !self.pooling.enabled {
+            return self.linker.instantiate(store, module);
+        }
+        
+        let cached = self.instance_pres.lock().unwrap().get(hash).cloned();
+        let instance_pre = match cached {
+            Some(instance_pre) => {
+                self.counters.instance_pre_hits.fetch_add(1, Ordering::Relaxed);
+                instance_pre
+            }
+            None => {
+                self.counters.instance_pre_misses.fetch_add(1, Ordering::Relaxed);
+                let instance_pre = self.linker.instantiate_pre(module)?;
+                self.instance_pres.lock().unwrap().put(hash.to_string(), instance_pre.clone());
+                instance_pre
+            }
+        };
+        
+        instance_pre.instantiate(store)
+    }
+    
+    pub fn stats(&self) -> RuntimeStats {
+        RuntimeStats {
+            pooling_enabled: self.pooling.enabled,
+            max_instances: self.pooling.enabled.then_some(self.pooling.max_instances),
+            memory_bytes_per_slot: self.pooling.enabled.then_some(self.pooling.memory_bytes_per_slot),
+            active_instances: self.counters.active_instances.load(Ordering::Relaxed),
+            total_instantiations: self.counters.total_instantiations.load(Ordering::Relaxed),
+            cached_modules: self.modules.len(),
+            cached_instance_pres: self.instance_pres.lock().unwrap().len(),
+            instance_pre_hits: self.counters.instance_pre_hits.load(Ordering::Relaxed),
+            instance_pre_misses: self.counters.instance_pre_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+static RUNTIME: OnceLock<PluginRuntime> = OnceLock::new();
+
+/// Builds the shared runtime from the loaded configuration; call it once at startup, before any execution.
+pub fn init_runtime(config: RuntimeConfig) -> Result<(), PluginError> {
+    let runtime = PluginRuntime::new(config)?;
+    RUNTIME
+        .set(runtime)
+        .map_err(|_| PluginError::Runtime("the plugin runtime is already initialized".to_string()))
+}
+
+// Callers that never load a `ServerConfig`, like the CLI, get the built-in defaults.
+pub fn runtime() -> &'static PluginRuntime {
+    RUNTIME.get_or_init(|| PluginRuntime::new(RuntimeConfig::default()).expect("Failed to initialize the plugin runtime"))
+}
+
+fn map_trap(e: wasmtime::Error, store: &mut Store<PluginState>, timeout_ms: u64) -> PluginError {
+    if let Some(violation) = store.data_mut().limiter.violation.take() {
+        return violation;
+    }
+    let e = match e.downcast::<PluginError>() {
+        Ok(error) => return error,
+        Err(e) => e,
+    };
+    match e.downcast_ref::<Trap>() {
+        Some(Trap::Interrupt) => PluginError::Timeout(timeout_ms),
+        _ => PluginError::Runtime(e.to_string()),
+    }
+}
+
+pub fn list_plugins(plugins_dir: &Path) -> Vec<String> {
     if !plugins_dir.exists() {
         return vec![];
     }
@@ -27,129 +623,179 @@ pub fn list_plugins() -> Vec<String> {
     }
 }
 
-pub fn run_plugin(plugin_path: &str) -> Result<String, Box<dyn std::error::Error>> {
+pub fn run_plugin(plugin_path: &str) -> Result<ExecutionOutput, PluginError> {
     run_plugin_with_params(plugin_path, None, None)
 }
 
 pub fn run_plugin_with_params(
     plugin_path: &str, 
-    _parameters: Option<Value>,
-    _timeout: Option<u64>
-) -> Result<String, Box<dyn std::error::Error>> {
-    let engine = Engine::default();
-    
-    let wasi = WasiCtxBuilder::new()
-        .inherit_stdio()
-        .inherit_args()?
-        .build();
+    parameters: Option<Value>,
+    timeout: Option<u64>
+) -> Result<ExecutionOutput, PluginError> {
+    run_plugin_with_options(plugin_path, ExecutionOptions {
+        parameters,
+        timeout,
+        ..Default::default()
+    })
+    .map_err(|failed| failed.error)
+}
+
+/// A failed run still reports its output so far and its peak linear memory.
+pub fn run_plugin_with_options(
+    plugin_path: &str,
+    options: ExecutionOptions,
+) -> Result<ExecutionOutput, FailedRun> {
+    let stdout = OutputBuffer::with_line_handler(
+        DEFAULT_MAX_OUTPUT_BYTES,
+        stream_handler(&options.on_output, LogStream::Stdout),
+    );
+    let stderr = OutputBuffer::with_line_handler(
+        DEFAULT_MAX_OUTPUT_BYTES,
+        stream_handler(&options.on_output, LogStream::Stderr),
+    );
     
-    let mut store = Store::new(&engine, wasi);
+    execute(plugin_path, options, stdout, stderr)
+}
+
+fn execute(
+    plugin_path: &str,
+    options: ExecutionOptions,
+    stdout: OutputBuffer,
+    stderr: OutputBuffer,
+) -> Result<ExecutionOutput, FailedRun> {
+    let timeout_ms = options.timeout.unwrap_or(DEFAULT_TIMEOUT_MS);
+    let cancel = options.cancel.clone();
     
-    let wasm_bytes = fs::read(plugin_path)?;
-    let module = Module::new(&engine, &wasm_bytes)?;
+    // Nothing has been allocated for the guest until the store exists.
+    let (mut store, hash, module) = prepare_store(plugin_path, options, timeout_ms, &stdout, &stderr)?;
+    let result = run_instance(&mut store, &hash, &module, cancel, timeout_ms);
     
-    let mut linker = Linker::new(&engine);
-    wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
+    let (stdout, stdout_truncated) = stdout.contents();
+    let (stderr, stderr_truncated) = stderr.contents();
+    let truncated = stdout_truncated || stderr_truncated;
+    let peak_memory_bytes = store.data().limiter.peak_memory_bytes as u64;
+    match result {
+        Ok(exit_code) => Ok(ExecutionOutput {
+            stdout,
+            stderr,
+            exit_code,
+            truncated,
+            peak_memory_bytes,
+        }),
+        Err(error) => Err(FailedRun {
+            error,
+            stdout,
+            stderr,
+            truncated,
+            peak_memory_bytes,
+        }),
+    }
+}
+
+fn prepare_store(
+    plugin_path: &str,
+    options: ExecutionOptions,
+    timeout_ms: u64,
+    stdout: &OutputBuffer,
+    stderr: &OutputBuffer,
+) -> Result<(Store<PluginState>, String, Module), PluginError> {
+    let runtime = runtime();
     
-    let instance = linker.instantiate(&mut store, &module)?;
+    if options.cancel.is_cancelled() {
+        return Err(PluginError::Cancelled);
+    }
     
-    let function_names = ["_start", "start", "main", "run"];
+    // Limits stored before the pool was shrunk would otherwise fail as an opaque allocation error.
+    runtime
+        .check_limits(&options.limits)
+        .map_err(|e| PluginError::Runtime(format!("Plugin limits do not fit the instance pool: {}", e)))?;
     
-    let mut executed = false;
-    let mut result = String::new();
+    // The guest sees only its own name as argv, never the server's command line.
+    let program = Path::new(plugin_path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "plugin".to_string());
+    let mut builder = WasiCtxBuilder::new();
+    builder
+        .stdout(Box::new(WritePipe::new(stdout.clone())))
+        .stderr(Box::new(WritePipe::new(stderr.clone())))
+        .arg(&program)
+        .map_err(|e| PluginError::Runtime(e.to_string()))?;
     
-    for func_name in &function_names {
-        if let Ok(func) = instance.get_typed_func::<(), ()>(&mut store, func_name) {
-            func.call(&mut store, ())?;
-            executed = true;
-            result = format!("Plugin executed successfully using function '{}'", func_name);
-            break;
-        }
+    if let Some(pipe) = options.stdin.clone() {
+        builder.stdin(Box::new(ReadPipe::new(StdinReader {
+            pipe,
+            buffered: Vec::new(),
+            cancel: options.cancel.clone(),
+            deadline: Instant::now().checked_add(Duration::from_millis(timeout_ms)),
+        })));
     }
     
-    if !executed {
-        return Err("No suitable entry function found in WASM module".into());
+    if let Some(parameters) = &options.parameters {
+        builder
+            .env(PARAMETERS_ENV, &parameters.to_string())
+            .map_err(|e| PluginError::Runtime(e.to_string()))?;
     }
     
-    Ok(result)
+    let state = PluginState {
+        wasi: builder.build(),
+        limiter: PluginLimiter::new(options.limits),
+    };
+    
+    let (hash, module) = runtime.load_module(plugin_path)?;
+    
+    let mut store = Store::new(&runtime.engine, state);
+    store.limiter(|state| &mut state.limiter);
+    Ok((store, hash, module))
 }
 
-pub async fn run_plugin_with_realtime_output(
-    plugin_path: &str,
-    _parameters: Option<Value>,
-    _timeout: Option<u64>,
-    ws_tx: broadcast::Sender<crate::websocket::PluginExecutionSession>,
-    session_id: &str,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let session_id = session_id.to_string();
-    let plugin_id = Path::new(plugin_path)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("unknown")
-        .to_string();
-    
-    let _ = ws_tx.send(crate::websocket::PluginExecutionSession {
-        id: session_id.clone(),
-        plugin_id: plugin_id.clone(),
-        status: "starting".to_string(),
-        output: "Plugin execution started".to_string(),
+fn run_instance(
+    store: &mut Store<PluginState>,
+    hash: &str,
+    module: &Module,
+    cancel: CancelToken,
+    timeout_ms: u64,
+) -> Result<i32, PluginError> {
+    let runtime = runtime();
+    let timeout_ticks = timeout_ms.div_ceil(EPOCH_TICK_MS).max(1);
+    let mut elapsed_ticks = 0;
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if cancel.is_cancelled() {
+            return Err(PluginError::Cancelled.into());
+        }
+        elapsed_ticks += 1;
+        if elapsed_ticks >= timeout_ticks {
+            return Err(Trap::Interrupt.into());
+        }
+        Ok(UpdateDeadline::Continue(1))
     });
     
-    let output = tokio::process::Command::new("cargo")
-        .args(&["run", "--bin", "execute_plugin", "--quiet"])
-        .arg(plugin_path)
-        .output()
-        .await?;
-    
-    let _ = ws_tx.send(crate::websocket::PluginExecutionSession {
-        id: session_id.clone(),
-        plugin_id: plugin_id.clone(),
-        status: "running".to_string(),
-        output: "Executing plugin...".to_string(),
-    });
+    let _active = ActiveInstance::enter(&runtime.counters.active_instances);
     
-    if !output.stdout.is_empty() {
-        let stdout_str = String::from_utf8_lossy(&output.stdout);
-        for line in stdout_str.lines() {
-            if !line.trim().is_empty() {
-                let _ = ws_tx.send(crate::websocket::PluginExecutionSession {
-                    id: session_id.clone(),
-                    plugin_id: plugin_id.clone(),
-                    status: "running".to_string(),
-                    output: line.trim().to_string(),
-                });
-            }
-        }
-    }
+    let instance = match runtime.instantiate(store, hash, module) {
+        Ok(instance) => instance,
+        Err(e) => return Err(map_trap(e, store, timeout_ms)),
+    };
     
-    if !output.stderr.is_empty() {
-        let stderr_str = String::from_utf8_lossy(&output.stderr);
-        for line in stderr_str.lines() {
-            if !line.trim().is_empty() {
-                let _ = ws_tx.send(crate::websocket::PluginExecutionSession {
-                    id: session_id.clone(),
-                    plugin_id: plugin_id.clone(),
-                    status: "running".to_string(),
-                    output: format!("ERROR: {}", line.trim()),
-                });
-            }
-        }
-    }
+    let function_names = ["_start", "start", "main", "run"];
     
-    let result = if output.status.success() {
-        "Plugin executed successfully".to_string()
-    } else {
-        format!("Plugin execution failed with exit code: {}", output.status)
-    };
+    let mut exit_code = None;
     
-    let _ = ws_tx.send(crate::websocket::PluginExecutionSession {
-        id: session_id.clone(),
-        plugin_id: plugin_id.clone(),
-        status: "completed".to_string(),
-        output: format!("Plugin completed: {}", result),
-    });
+    for func_name in &function_names {
+        if let Ok(func) = instance.get_typed_func::<(), ()>(&mut *store, func_name) {
+            exit_code = Some(match func.call(&mut *store, ()) {
+                Ok(()) => 0,
+                Err(e) => match e.downcast_ref::<I32Exit>() {
+                    Some(exit) => exit.0,
+                    None => return Err(map_trap(e, store, timeout_ms)),
+                },
+            });
+            break;
+        }
+    }
     
-    Ok(result)
+    exit_code.ok_or(PluginError::NoEntryPoint)
 }
 
 pub fn get_plugin_info(plugin_path: &str) -> Result<PluginInfo, Box<dyn std::error::Error>> {
@@ -162,9 +808,7 @@ pub fn get_plugin_info(plugin_path: &str) -> Result<PluginInfo, Box<dyn std::err
     let metadata = fs::metadata(path)?;
     let file_size = metadata.len();
     
-    let wasm_bytes = fs::read(path)?;
-    let engine = Engine::default();
-    let module = Module::new(&engine, &wasm_bytes)?;
+    let (_, module) = runtime().load_module(plugin_path)?;
     
     let exports: Vec<String> = module
         .exports()
@@ -191,3 +835,311 @@ pub struct PluginInfo {
     pub exports: Vec<String>,
     pub has_start: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collecting_buffer(limit: usize) -> (OutputBuffer, Arc<Mutex<Vec<String>>>) {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let buffer = OutputBuffer::with_line_handler(
+            limit,
+            Some(Arc::new(move |line: &str| sink.lock().unwrap().push(line.to_string()))),
+        );
+        (buffer, lines)
+    }
+
+    #[test]
+    fn output_cap_truncates_capture_but_not_streamed_lines() {
+        let (mut buffer, lines) = collecting_buffer(8);
+        buffer.write_all(b"first\nsecond\nthird\n").unwrap();
+
+        let (captured, truncated) = buffer.contents();
+        assert_eq!(captured, "first\nse");
+        assert!(truncated);
+        assert_eq!(*lines.lock().unwrap(), ["first", "second", "third"]);
+    }
+
+    #[test]
+    fn unterminated_last_line_is_streamed_on_contents() {
+        let (mut buffer, lines) = collecting_buffer(DEFAULT_MAX_OUTPUT_BYTES);
+        buffer.write_all(b"partial").unwrap();
+        assert!(lines.lock().unwrap().is_empty());
+
+        let (captured, truncated) = buffer.contents();
+        assert_eq!(captured, "partial");
+        assert!(!truncated);
+        assert_eq!(*lines.lock().unwrap(), ["partial"]);
+    }
+
+    #[test]
+    fn overlong_line_is_streamed_in_pieces() {
+        let (mut buffer, lines) = collecting_buffer(16);
+        buffer.write_all(&vec![b'x'; MAX_LINE_BYTES + 10]).unwrap();
+        buffer.contents();
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), MAX_LINE_BYTES);
+        assert_eq!(lines[1].len(), 10);
+    }
+
+    // Guest written to a scratch file in the text format, which wasmtime compiles as well.
+    struct TestPlugin(std::path::PathBuf);
+
+    impl TestPlugin {
+        fn new(wat: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("sandcrate-test-{}.wat", uuid::Uuid::new_v4()));
+            fs::write(&path, wat).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TestPlugin {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    // Writes one line to stdout, then spins until it is interrupted.
+    const PRINT_AND_SPIN: &str = r#"
+        (module
+          (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 16) "tick\n")
+          (func (export "_start")
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const 5))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (loop (br 0))))
+    "#;
+
+    #[test]
+    fn epoch_deadline_times_out_a_spinning_guest_and_keeps_its_output() {
+        let plugin = TestPlugin::new(PRINT_AND_SPIN);
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+
+        let started = Instant::now();
+        let failed = run_plugin_with_options(plugin.path(), ExecutionOptions {
+            timeout: Some(50),
+            on_output: Some(Arc::new(move |stream, line: &str| sink.lock().unwrap().push((stream, line.to_string())))),
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(matches!(failed.error, PluginError::Timeout(50)), "{}", failed.error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(failed.stdout, "tick\n");
+        assert_eq!(*lines.lock().unwrap(), [(LogStream::Stdout, "tick".to_string())]);
+    }
+
+    #[test]
+    fn cancelling_interrupts_a_running_guest() {
+        let plugin = TestPlugin::new(PRINT_AND_SPIN);
+        let cancel = CancelToken::default();
+        let canceller = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        let failed = run_plugin_with_options(plugin.path(), ExecutionOptions {
+            timeout: Some(60_000),
+            cancel,
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(matches!(failed.error, PluginError::Cancelled), "{}", failed.error);
+        assert_eq!(failed.error.execution_status(), ExecutionStatus::Cancelled);
+        assert_eq!(failed.stdout, "tick\n");
+    }
+
+    #[test]
+    fn cancelled_token_stops_the_run_before_it_starts() {
+        let plugin = TestPlugin::new(PRINT_AND_SPIN);
+        let cancel = CancelToken::default();
+        cancel.cancel();
+
+        let failed = run_plugin_with_options(plugin.path(), ExecutionOptions { cancel, ..Default::default() }).unwrap_err();
+        assert!(matches!(failed.error, PluginError::Cancelled), "{}", failed.error);
+        assert!(failed.stdout.is_empty());
+    }
+
+    // Starts with one page of linear memory and grows by `pages` more.
+    fn growing_plugin(pages: u8) -> TestPlugin {
+        TestPlugin::new(&format!(
+            r#"
+            (module
+              (memory 1)
+              (func (export "_start")
+                (drop (memory.grow (i32.const {})))))
+            "#,
+            pages
+        ))
+    }
+
+    #[test]
+    fn limiter_records_the_peak_memory_of_a_successful_run() {
+        let plugin = growing_plugin(3);
+        let output = run_plugin_with_options(plugin.path(), ExecutionOptions {
+            limits: ResourceLimits { max_memory_bytes: 4 * WASM_PAGE_SIZE, ..Default::default() },
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(output.exit_code, 0);
+        assert_eq!(output.peak_memory_bytes, 4 * WASM_PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn limiter_fails_the_run_when_memory_grows_past_the_limit() {
+        let plugin = growing_plugin(4);
+        let failed = run_plugin_with_options(plugin.path(), ExecutionOptions {
+            limits: ResourceLimits { max_memory_bytes: 4 * WASM_PAGE_SIZE, ..Default::default() },
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(matches!(failed.error, PluginError::MemoryLimitExceeded(limit) if limit == 4 * WASM_PAGE_SIZE), "{}", failed.error);
+        assert_eq!(failed.error.execution_status(), ExecutionStatus::Failed);
+        assert!(failed.error.to_string().contains("memory limit exceeded"), "{}", failed.error);
+        // The refused growth never happened, so the peak is the initial page.
+        assert_eq!(failed.peak_memory_bytes, WASM_PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn limiter_refuses_tables_larger_than_the_limit() {
+        let mut limiter = PluginLimiter::new(ResourceLimits { max_memory_bytes: WASM_PAGE_SIZE, max_table_elements: 10 });
+
+        assert!(limiter.table_growing(0, 10, None).unwrap());
+        assert!(limiter.table_growing(10, 11, None).is_err());
+        assert!(matches!(limiter.violation, Some(PluginError::TableLimitExceeded(10))));
+    }
+
+    #[test]
+    fn stdin_handle_refuses_writes_beyond_the_queue_and_after_close() {
+        let (handle, _pipe) = stdin_pipe();
+        for _ in 0..STDIN_QUEUE {
+            handle.write(b"x".to_vec()).unwrap();
+        }
+        assert_eq!(handle.write(b"x".to_vec()), Err(StdinError::Full));
+
+        handle.close();
+        assert_eq!(handle.write(b"x".to_vec()), Err(StdinError::Closed));
+
+        let (handle, pipe_of_stopped_plugin) = stdin_pipe();
+        drop(pipe_of_stopped_plugin);
+        assert_eq!(handle.write(b"x".to_vec()), Err(StdinError::Closed));
+    }
+
+    fn stdin_reader(pipe: StdinPipe, cancel: CancelToken) -> StdinReader {
+        StdinReader {
+            pipe,
+            buffered: Vec::new(),
+            cancel,
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn stdin_reader_drains_queued_input_before_reporting_eof() {
+        let (handle, pipe) = stdin_pipe();
+        handle.write(b"hello ".to_vec()).unwrap();
+        handle.write(b"world".to_vec()).unwrap();
+        handle.close();
+
+        let mut reader = stdin_reader(pipe, CancelToken::default());
+        let mut buf = [0u8; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"hell");
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "o world");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn cancelling_ends_a_blocked_stdin_read() {
+        // The handle stays open, so only the cancellation can end the read.
+        let (_handle, pipe) = stdin_pipe();
+        let cancel = CancelToken::default();
+        let canceller = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        let mut buf = [0u8; 4];
+        assert_eq!(stdin_reader(pipe, cancel).read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn guest_reads_stdin_until_it_is_closed() {
+        // Copies stdin to stdout in chunks until a read returns no bytes.
+        let plugin = TestPlugin::new(
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (func (export "_start")
+                (i32.store (i32.const 0) (i32.const 64))
+                (loop $next
+                  (i32.store (i32.const 4) (i32.const 1024))
+                  (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+                  (if (i32.load (i32.const 8))
+                    (then
+                      (i32.store (i32.const 4) (i32.load (i32.const 8)))
+                      (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                      (br $next))))))
+            "#,
+        );
+        let (handle, pipe) = stdin_pipe();
+        std::thread::spawn(move || {
+            handle.write(b"hello\n".to_vec()).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            handle.write(b"world\n".to_vec()).unwrap();
+            handle.close();
+        });
+
+        let output = run_plugin_with_options(plugin.path(), ExecutionOptions {
+            timeout: Some(10_000),
+            stdin: Some(pipe),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(output.exit_code, 0);
+        assert_eq!(output.stdout, "hello\nworld\n");
+    }
+
+    #[test]
+    fn pooled_runtime_rejects_limits_larger_than_a_slot() {
+        let runtime = PluginRuntime::new(RuntimeConfig {
+            limits: ResourceLimits {
+                max_memory_bytes: 1024 * 1024,
+                max_table_elements: 100,
+            },
+            pooling: PoolingConfig {
+                enabled: true,
+                max_instances: 1,
+                memory_bytes_per_slot: 2 * 1024 * 1024,
+            },
+            module_cache: ModuleCacheConfig::default(),
+        })
+        .unwrap();
+
+        let fits = ResourceLimits { max_memory_bytes: 2 * 1024 * 1024, max_table_elements: 100 };
+        assert!(runtime.check_limits(&fits).is_ok());
+        let too_much_memory = ResourceLimits { max_memory_bytes: 3 * 1024 * 1024, ..fits };
+        assert!(runtime.check_limits(&too_much_memory).unwrap_err().contains("PLUGIN_POOL_MEMORY_MB"));
+        let too_many_elements = ResourceLimits { max_table_elements: 101, ..fits };
+        assert!(runtime.check_limits(&too_many_elements).unwrap_err().contains("PLUGIN_MAX_TABLE_ELEMENTS"));
+    }
+}
@@ -1,20 +1,136 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::sync::broadcast;
 use uuid::Uuid;
-use chrono::Utc;
 use serde_json::Value;
 
+use crate::config::ServerConfig;
 use crate::database::{
-    PluginRepository, PostgresPluginRepository, CreatePluginRequest, UpdatePluginRequest,
-    CreateExecutionRequest, Plugin, PluginExecution, PluginStatus, ExecutionStatus
+    PluginRepository, CreatePluginRequest, UpdatePluginRequest,
+    CreateExecutionRequest, FinishExecutionRequest, ExecutionHistoryFilter, ExecutionPage, Plugin,
+    PluginExecution, ExecutionStatus, ExecutionLogFilter, ExecutionLogLine,
+    EXECUTION_CANCEL_CHANNEL, EXECUTION_LOGS_CHANNEL
 };
+use crate::execution_logs::{self, ExecutionLogConfig, ExecutionLogger};
+use crate::plugin::{self, CancelToken, ExecutionOutput, FailedRun, ResourceLimits};
 
 pub struct PluginService {
     repo: Arc<dyn PluginRepository + Send + Sync>,
+    running: Mutex<HashMap<Uuid, CancelToken>>,
+    logs_tx: broadcast::Sender<Uuid>,
+    log_config: ExecutionLogConfig,
+    plugins_dir: PathBuf,
+    max_plugin_size: usize,
+    max_execution_timeout_ms: u64,
 }
 
 impl PluginService {
-    pub fn new(repo: Arc<dyn PluginRepository + Send + Sync>) -> Self {
-        Self { repo }
+    pub fn new(repo: Arc<dyn PluginRepository + Send + Sync>, config: &ServerConfig) -> Self {
+        Self {
+            repo,
+            running: Mutex::new(HashMap::new()),
+            logs_tx: broadcast::channel(256).0,
+            log_config: config.execution_logs.clone(),
+            plugins_dir: config.plugins_dir.clone(),
+            max_plugin_size: config.max_plugin_size_bytes,
+            max_execution_timeout_ms: config.max_execution_timeout_ms,
+        }
+    }
+
+    pub fn plugins_dir(&self) -> &Path {
+        &self.plugins_dir
+    }
+
+    pub fn max_plugin_size(&self) -> usize {
+        self.max_plugin_size
+    }
+
+    /// Refuses a requested timeout above the configured maximum; `None` uses the default timeout.
+    pub fn check_timeout(&self, timeout_ms: Option<u64>) -> Result<(), String> {
+        match timeout_ms {
+            Some(timeout_ms) if timeout_ms > self.max_execution_timeout_ms => Err(format!(
+                "Timeout must not exceed {} ms",
+                self.max_execution_timeout_ms
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn execution_logger(&self, execution_id: Uuid) -> ExecutionLogger {
+        ExecutionLogger::start(self.repo.clone(), execution_id, &self.log_config)
+    }
+
+    pub fn start_log_retention(&self) {
+        execution_logs::start_retention(self.repo.clone(), &self.log_config);
+    }
+
+    pub async fn get_execution_logs(&self, execution_id: Uuid, filter: ExecutionLogFilter) -> Result<Vec<ExecutionLogLine>, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.get_execution_logs(execution_id, filter).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    /// Yields the id of every execution that got new log lines, in any process.
+    pub fn subscribe_logs(&self) -> broadcast::Receiver<Uuid> {
+        self.logs_tx.subscribe()
+    }
+
+    /// Registers an execution running in this process with a token the caller already holds, so
+    /// both can stop the run until its outcome is recorded.
+    pub fn track_execution(&self, execution_id: Uuid, token: CancelToken) -> CancelToken {
+        self.running.lock().unwrap().insert(execution_id, token.clone());
+        token
+    }
+
+    fn cancel_local_execution(&self, execution_id: Uuid) -> bool {
+        match self.running.lock().unwrap().get(&execution_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels the execution if it runs in this process, otherwise asks the other processes to.
+    pub async fn request_cancellation(&self, execution_id: Uuid) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.cancel_local_execution(execution_id) {
+            return Ok(());
+        }
+        self.repo.request_execution_cancel(execution_id).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    /// Follows cancel requests and stored log lines of executions in every process.
+    pub fn start_listener(self: &Arc<Self>, pool: PgPool) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = service.listen(&pool).await {
+                    eprintln!("Execution listener failed: {}", e);
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        });
+    }
+
+    async fn listen(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen_all([EXECUTION_CANCEL_CHANNEL, EXECUTION_LOGS_CHANNEL]).await?;
+
+        loop {
+            let notification = listener.recv().await?;
+            let Ok(id) = Uuid::parse_str(notification.payload()) else {
+                continue;
+            };
+            if notification.channel() == EXECUTION_CANCEL_CHANNEL {
+                self.cancel_local_execution(id);
+            } else {
+                let _ = self.logs_tx.send(id);
+            }
+        }
     }
 
     pub async fn list_plugins(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Plugin>, Box<dyn std::error::Error + Send + Sync>> {
@@ -38,6 +154,7 @@ impl PluginService {
     }
 
     pub async fn update_plugin(&self, id: Uuid, request: UpdatePluginRequest) -> Result<Plugin, Box<dyn std::error::Error + Send + Sync>> {
+        check_plugin_limits(&request)?;
         self.repo.update_plugin(id, request).await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
     }
@@ -59,17 +176,80 @@ impl PluginService {
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
     }
 
-    pub async fn get_execution_history(&self, plugin_id: Uuid, limit: Option<i64>) -> Result<Vec<PluginExecution>, Box<dyn std::error::Error + Send + Sync>> {
-        self.repo.get_execution_history(plugin_id, limit).await
+    pub async fn complete_execution(&self, id: Uuid, outcome: FinishExecutionRequest) -> Result<PluginExecution, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.complete_execution(id, outcome).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    pub async fn fail_execution(&self, id: Uuid, status: ExecutionStatus, outcome: FinishExecutionRequest) -> Result<PluginExecution, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.fail_execution(id, status, outcome).await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
     }
 
-    pub async fn sync_plugins_from_filesystem(&self, plugins_dir: &str) -> Result<Vec<Plugin>, Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn cancel_execution(&self, id: Uuid, outcome: FinishExecutionRequest) -> Result<PluginExecution, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.cancel_execution(id, outcome).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    pub async fn record_execution_outcome(&self, id: Uuid, outcome: &Result<ExecutionOutput, FailedRun>, execution_time_ms: i64) -> Result<PluginExecution, Box<dyn std::error::Error + Send + Sync>> {
+        self.running.lock().unwrap().remove(&id);
+        
+        match outcome {
+            Ok(output) => {
+                let request = FinishExecutionRequest {
+                    result: Some(output.stdout.clone()),
+                    stderr: Some(output.stderr.clone()),
+                    exit_code: Some(output.exit_code),
+                    output_truncated: output.truncated,
+                    error: (!output.success()).then(|| format!("Plugin exited with code {}", output.exit_code)),
+                    execution_time_ms,
+                    peak_memory_bytes: Some(output.peak_memory_bytes as i64),
+                };
+                if output.success() {
+                    self.complete_execution(id, request).await
+                } else {
+                    self.fail_execution(id, ExecutionStatus::Failed, request).await
+                }
+            }
+            // Output written before a timeout or cancellation is kept with the error.
+            Err(failed) => {
+                let request = FinishExecutionRequest {
+                    result: Some(failed.stdout.clone()),
+                    stderr: Some(failed.stderr.clone()),
+                    output_truncated: failed.truncated,
+                    error: Some(failed.error.to_string()),
+                    execution_time_ms,
+                    peak_memory_bytes: Some(failed.peak_memory_bytes as i64),
+                    ..Default::default()
+                };
+                match failed.error.execution_status() {
+                    ExecutionStatus::Cancelled => self.cancel_execution(id, request).await,
+                    status => self.fail_execution(id, status, request).await,
+                }
+            }
+        }
+    }
+
+    pub async fn get_execution(&self, id: Uuid) -> Result<Option<PluginExecution>, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.get_execution(id).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    pub async fn get_execution_by_session(&self, session_id: &str) -> Result<Option<PluginExecution>, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.get_execution_by_session(session_id).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    pub async fn get_execution_history(&self, plugin_id: Uuid, filter: ExecutionHistoryFilter) -> Result<ExecutionPage, Box<dyn std::error::Error + Send + Sync>> {
+        self.repo.get_execution_history(plugin_id, filter).await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    }
+
+    pub async fn sync_plugins_from_filesystem(&self) -> Result<Vec<Plugin>, Box<dyn std::error::Error + Send + Sync>> {
         use std::fs;
-        use std::path::Path;
         
         let mut synced_plugins = Vec::new();
-        let plugins_path = Path::new(plugins_dir);
+        let plugins_path = self.plugins_dir.as_path();
         
         if !plugins_path.exists() {
             return Ok(synced_plugins);
@@ -114,4 +294,13 @@ impl PluginService {
         
         Ok(synced_plugins)
     }
-} 
\ No newline at end of file
+} 
+
+// Rejected when stored rather than on every later run of the plugin.
+fn check_plugin_limits(request: &UpdatePluginRequest) -> Result<(), String> {
+    if request.max_memory_bytes.is_some_and(|b| b <= 0) || request.max_table_elements.is_some_and(|e| e <= 0) {
+        return Err("Plugin limits must be positive".to_string());
+    }
+    let limits = ResourceLimits::with_overrides(request.max_memory_bytes, request.max_table_elements);
+    plugin::runtime().check_limits(&limits)
+}
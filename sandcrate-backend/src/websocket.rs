@@ -1,180 +1,473 @@
 use axum::{
-    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State, Query},
+    extract::{ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade}, State, Query},
     response::IntoResponse,
 };
-use serde_json::json;
-use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use serde::Deserialize;
 
-use crate::auth::AuthConfig;
-use crate::plugin;
+use crate::auth::{self, AuthConfig, AuthError, UserInfo};
+use crate::database::{CreateJobRequest, ExecutionLogFilter, ExecutionStatus, JobInputOutcome, JobStatus, LogStream};
+use crate::jobs::{JobQueue, JobQueueError};
+use crate::services::PluginService;
+use crate::ws_protocol::{
+    ClientCommand, ErrorCode, ExecutionResult, ServerEvent, SessionStatus, MAX_INPUT_BYTES, PROTOCOL_VERSION,
+};
+
+// Finished sessions stay subscribable for a while so a client that just missed the end still gets it.
+const SESSION_RETENTION: Duration = Duration::from_secs(300);
+
+// Most recent events kept per session for clients that subscribe late or reconnect.
+const SESSION_REPLAY_EVENTS: usize = 1000;
+
+// Events waiting to be written to one socket; a client further behind gets a `lagged` event.
+const OUTBOUND_QUEUE: usize = 256;
+
+// Log lines fetched at once while following the output of a session's execution.
+const FOLLOW_PAGE: i64 = 500;
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    pub ping_interval: Duration,
+    // A peer that has not answered for this long after a ping is considered dead.
+    pub pong_timeout: Duration,
+    // Sockets without commands or session updates for this long are closed; `None` disables it.
+    pub idle_timeout: Option<Duration>,
+    pub max_connections: usize,
+    pub max_connections_per_user: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: Some(Duration::from_secs(600)),
+            max_connections: 1000,
+            max_connections_per_user: 5,
+        }
+    }
+}
+
+pub type WebSocketState = (Arc<AuthConfig>, Arc<WebSocketManager>, Arc<PluginService>, Arc<JobQueue>);
 
 #[derive(Debug, Deserialize)]
 pub struct WebSocketQuery {
     token: Option<String>,
+    protocol: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
 pub struct PluginExecutionSession {
     pub id: String,
     pub plugin_id: String,
-    pub status: String,
+    pub status: SessionStatus,
+    pub stream: Option<LogStream>,
     pub output: String,
+    pub result: Option<ExecutionResult>,
+}
+
+impl PluginExecutionSession {
+    fn into_event(self, seq: u64) -> ServerEvent {
+        match self.result {
+            Some(result) => ServerEvent::Result {
+                seq,
+                session_id: self.id,
+                plugin_id: self.plugin_id,
+                status: self.status,
+                result,
+            },
+            None => ServerEvent::Update {
+                seq,
+                session_id: self.id,
+                plugin_id: self.plugin_id,
+                status: self.status,
+                stream: self.stream,
+                output: self.output,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SessionEvent {
+    seq: u64,
+    session: PluginExecutionSession,
+}
+
+struct SessionLog {
+    next_seq: u64,
+    events: VecDeque<SessionEvent>,
+}
+
+struct SessionChannel {
+    log: Mutex<SessionLog>,
+    tx: broadcast::Sender<SessionEvent>,
+}
+
+/// Publishes the updates of one execution session, numbering them and keeping them for replay.
+#[derive(Clone)]
+pub struct SessionSink(Arc<SessionChannel>);
+
+impl SessionSink {
+    pub fn send(&self, session: PluginExecutionSession) {
+        let mut log = self.0.log.lock().unwrap();
+        let event = SessionEvent {
+            seq: log.next_seq,
+            session,
+        };
+        log.next_seq += 1;
+        if log.events.len() == SESSION_REPLAY_EVENTS {
+            log.events.pop_front();
+        }
+        log.events.push_back(event.clone());
+        // Sent under the lock so subscribers never see events out of sequence.
+        let _ = self.0.tx.send(event);
+    }
+}
+
+struct SessionEntry {
+    plugin_id: String,
+    owner: String,
+    channel: Arc<SessionChannel>,
+    // The job running the session on a worker, which receives its stdin.
+    job_id: Uuid,
+}
+
+impl SessionEntry {
+    fn is_visible_to(&self, user: &UserInfo) -> bool {
+        user.is_admin || self.owner == user.username
+    }
+}
+
+struct Subscription {
+    plugin_id: String,
+    backlog: Vec<SessionEvent>,
+    // The requested events are older than anything still buffered.
+    truncated: bool,
+    rx: broadcast::Receiver<SessionEvent>,
+}
+
+#[derive(Default)]
+struct ConnectionCounts {
+    total: usize,
+    per_user: HashMap<String, usize>,
+}
+
+/// Holds one of the connection slots counted against the limits until dropped.
+struct ConnectionGuard {
+    manager: Arc<WebSocketManager>,
+    username: String,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.manager.connections.lock().unwrap();
+        connections.total -= 1;
+        if let Some(count) = connections.per_user.get_mut(&self.username) {
+            *count -= 1;
+            if *count == 0 {
+                connections.per_user.remove(&self.username);
+            }
+        }
+    }
 }
 
 pub struct WebSocketManager {
-    tx: broadcast::Sender<PluginExecutionSession>,
+    config: WebSocketConfig,
+    sessions: Mutex<HashMap<String, SessionEntry>>,
+    connections: Mutex<ConnectionCounts>,
 }
 
 impl WebSocketManager {
-    pub fn new() -> Self {
-        let (tx, _) = broadcast::channel(100);
-        Self { tx }
+    pub fn new(config: WebSocketConfig) -> Self {
+        Self {
+            config,
+            sessions: Mutex::new(HashMap::new()),
+            connections: Mutex::new(ConnectionCounts::default()),
+        }
+    }
+
+    fn open_connection(self: &Arc<Self>, user: &UserInfo) -> Result<ConnectionGuard, &'static str> {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.total >= self.config.max_connections {
+            return Err("Server connection limit reached");
+        }
+        let count = connections.per_user.entry(user.username.clone()).or_insert(0);
+        if *count >= self.config.max_connections_per_user {
+            return Err("Too many connections for this user");
+        }
+        *count += 1;
+        connections.total += 1;
+
+        Ok(ConnectionGuard {
+            manager: self.clone(),
+            username: user.username.clone(),
+        })
     }
 
-    pub fn get_sender(&self) -> broadcast::Sender<PluginExecutionSession> {
-        self.tx.clone()
+    fn create_session(
+        &self,
+        session_id: &str,
+        plugin_id: &str,
+        owner: &UserInfo,
+        job_id: Uuid,
+    ) -> SessionSink {
+        let (tx, _) = broadcast::channel(256);
+        let channel = Arc::new(SessionChannel {
+            log: Mutex::new(SessionLog {
+                next_seq: 0,
+                events: VecDeque::new(),
+            }),
+            tx,
+        });
+        self.sessions.lock().unwrap().insert(session_id.to_string(), SessionEntry {
+            plugin_id: plugin_id.to_string(),
+            owner: owner.username.clone(),
+            channel: channel.clone(),
+            job_id,
+        });
+        SessionSink(channel)
+    }
+
+    fn subscribe(
+        &self,
+        session_id: &str,
+        user: &UserInfo,
+        since_seq: Option<u64>,
+    ) -> Option<Subscription> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(session_id)
+            .filter(|session| session.is_visible_to(user))?;
+
+        // Holding the log lock keeps the backlog and the live receiver contiguous.
+        let log = session.channel.log.lock().unwrap();
+        let rx = session.channel.tx.subscribe();
+        let first_seq = log.events.front().map_or(log.next_seq, |event| event.seq);
+        let backlog = log
+            .events
+            .iter()
+            .filter(|event| since_seq.is_none_or(|seq| event.seq > seq))
+            .cloned()
+            .collect();
+
+        Some(Subscription {
+            plugin_id: session.plugin_id.clone(),
+            backlog,
+            truncated: since_seq.map_or(first_seq > 0, |seq| seq + 1 < first_seq),
+            rx,
+        })
+    }
+
+    fn session_job(&self, session_id: &str, user: &UserInfo) -> Option<Uuid> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .filter(|session| session.is_visible_to(user))
+            .map(|session| session.job_id)
+    }
+
+    fn finish_session(self: &Arc<Self>, session_id: String) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(SESSION_RETENTION).await;
+            manager.sessions.lock().unwrap().remove(&session_id);
+        });
     }
 }
 
 pub async fn plugin_execution_websocket(
     ws: WebSocketUpgrade,
-    State((state, ws_manager)): State<(Arc<AuthConfig>, Arc<WebSocketManager>)>,
+    State((state, ws_manager, plugin_service, job_queue)): State<WebSocketState>,
     Query(query): Query<WebSocketQuery>,
 ) -> impl IntoResponse {
-    if let Some(token) = query.token {
-        if !is_valid_token(&token, &state).await {
+    if let Some(version) = query.protocol {
+        if version != PROTOCOL_VERSION {
+            return axum::http::Response::builder()
+                .status(400)
+                .body(format!("Unsupported protocol version {}, server speaks {}", version, PROTOCOL_VERSION).into())
+                .unwrap();
+        }
+    }
+    
+    let user = match query.token.as_deref() {
+        Some(token) => auth::authenticate_token(&state, token).await.ok(),
+        None => None,
+    };
+    let user = match user {
+        Some(user) => user,
+        None => {
             return axum::http::Response::builder()
                 .status(401)
                 .body("Unauthorized".into())
                 .unwrap();
         }
-    }
+    };
+    let token = query.token.unwrap_or_default();
     
-    ws.on_upgrade(|socket| handle_plugin_execution_socket(socket, state, ws_manager))
+    // Limits are enforced after the upgrade so browsers can see the reason in the close frame.
+    match ws_manager.open_connection(&user) {
+        Ok(guard) => ws.on_upgrade(move |socket| async move {
+            handle_plugin_execution_socket(socket, state, token, ws_manager, plugin_service, job_queue, user).await;
+            drop(guard);
+        }),
+        Err(reason) => {
+            println!("Rejected WebSocket connection from {}: {}", user.username, reason);
+            ws.on_upgrade(move |socket| close_socket(socket, close_code::AGAIN, reason))
+        }
+    }
 }
 
-async fn is_valid_token(token: &str, _config: &AuthConfig) -> bool {
-    if token.is_empty() {
-        return false;
+async fn close_socket(mut socket: WebSocket, code: u16, reason: &'static str) {
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: Cow::Borrowed(reason),
+        })))
+        .await;
+}
+
+async fn send_event(socket: &mut WebSocket, event: &ServerEvent) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(event).expect("server events always serialize");
+    socket.send(Message::Text(text)).await
+}
+
+fn forward_session(
+    session_id: String,
+    subscription: Subscription,
+    out_tx: mpsc::Sender<ServerEvent>,
+) -> JoinHandle<()> {
+    let Subscription { backlog, mut rx, .. } = subscription;
+    tokio::spawn(async move {
+        let mut last_seq = None;
+
+        // The backlog is already bounded by the replay buffer, so it may wait for room.
+        for SessionEvent { seq, session } in backlog {
+            if out_tx.send(session.into_event(seq)).await.is_err() {
+                return;
+            }
+            last_seq = Some(seq);
+        }
+
+        // Nothing is buffered for a slow client: once it falls behind it is told the last `seq` it
+        // got and resubscribes with `since_seq` to catch up from the replay buffer.
+        loop {
+            let SessionEvent { seq, session } = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => break,
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if last_seq.is_some_and(|last| seq <= last) {
+                continue;
+            }
+            match out_tx.try_send(session.into_event(seq)) {
+                Ok(()) => last_seq = Some(seq),
+                Err(mpsc::error::TrySendError::Full(_)) => break,
+                Err(mpsc::error::TrySendError::Closed(_)) => return,
+            }
+        }
+
+        let _ = out_tx.send(ServerEvent::Lagged { session_id, last_seq }).await;
+    })
+}
+
+// The token is checked again before every command and on every ping, so a socket does not outlive
+// the expiry or revocation of the token it was opened with, and role changes apply immediately.
+async fn reauthenticate(state: &AuthConfig, token: &str, user: &mut UserInfo) -> bool {
+    match auth::authenticate_token(state, token).await {
+        Ok(current) => {
+            *user = current;
+            true
+        }
+        // A database hiccup should not drop every connected client.
+        Err(AuthError::Database(e)) => {
+            eprintln!("Failed to re-check WebSocket token of {}: {}", user.username, e);
+            true
+        }
+        Err(e) => {
+            println!("Closing WebSocket of {}: {}", user.username, e);
+            false
+        }
     }
-    
-    true
+}
+
+// Why a socket is closed at a heartbeat: its peer stopped answering pings, or nothing happened on
+// it for longer than the idle timeout.
+fn heartbeat_close_reason(
+    config: &WebSocketConfig,
+    now: Instant,
+    last_seen: Instant,
+    last_activity: Instant,
+) -> Option<&'static str> {
+    if now.duration_since(last_seen) > config.ping_interval + config.pong_timeout {
+        return Some("Ping timeout");
+    }
+    if config.idle_timeout.is_some_and(|idle| now.duration_since(last_activity) > idle) {
+        return Some("Idle timeout");
+    }
+    None
 }
 
 async fn handle_plugin_execution_socket(
     mut socket: WebSocket,
-    _state: Arc<AuthConfig>,
+    state: Arc<AuthConfig>,
+    token: String,
     ws_manager: Arc<WebSocketManager>,
+    plugin_service: Arc<PluginService>,
+    job_queue: Arc<JobQueue>,
+    mut user: UserInfo,
 ) {
-    let mut rx = ws_manager.get_sender().subscribe();
+    let (out_tx, mut out_rx) = mpsc::channel(OUTBOUND_QUEUE);
+    let mut subscriptions: HashMap<String, JoinHandle<()>> = HashMap::new();
     
-    let connect_msg = json!({
-        "type": "connected",
-        "message": "WebSocket connected successfully"
-    });
+    let connected = ServerEvent::Connected {
+        protocol_version: PROTOCOL_VERSION,
+        username: user.username.clone(),
+        message: "WebSocket connected successfully".to_string(),
+    };
     
-    if let Err(_) = socket.send(Message::Text(connect_msg.to_string())).await {
+    if send_event(&mut socket, &connected).await.is_err() {
         return;
     }
+    
+    let config = ws_manager.config.clone();
+    let mut heartbeat = tokio::time::interval(config.ping_interval);
+    let mut last_seen = Instant::now();
+    let mut last_activity = Instant::now();
 
     loop {
-        tokio::select! {
+        let reply = tokio::select! {
             msg = socket.recv() => {
+                last_seen = Instant::now();
                 match msg {
                     Some(Ok(Message::Text(text))) => {
-                        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&text) {
-                            if let Some(command) = data.get("command").and_then(|c| c.as_str()) {
-                                match command {
-                                    "execute_plugin" => {
-                                        if let (Some(plugin_id), parameters, timeout) = (
-                                            data.get("plugin_id").and_then(|p| p.as_str()),
-                                            data.get("parameters").cloned(),
-                                            data.get("timeout").and_then(|t| t.as_u64()),
-                                        ) {
-                                            let session_id = Uuid::new_v4().to_string();
-                                            
-                                            let initial_status = json!({
-                                                "type": "status",
-                                                "session_id": session_id,
-                                                "plugin_id": plugin_id,
-                                                "status": "starting",
-                                                "message": "Plugin execution started"
-                                            });
-                                            
-                                            if let Err(_) = socket.send(Message::Text(initial_status.to_string())).await {
-                                                break;
-                                            }
-
-                                            let ws_tx = ws_manager.get_sender();
-                                            let plugin_id = plugin_id.to_string();
-                                            
-                                            tokio::spawn(async move {
-                                                                                            let result = plugin::run_plugin_with_realtime_output(
-                                                &format!("../assets/plugins/{}.wasm", plugin_id),
-                                                parameters,
-                                                timeout,
-                                                ws_tx.clone(),
-                                                &session_id,
-                                            ).await;
-                                                
-                                                let final_message = match result {
-                                                    Ok(output) => json!({
-                                                        "type": "result",
-                                                        "session_id": session_id,
-                                                        "plugin_id": plugin_id,
-                                                        "status": "completed",
-                                                        "output": output,
-                                                        "success": true
-                                                    }),
-                                                    Err(e) => json!({
-                                                        "type": "result",
-                                                        "session_id": session_id,
-                                                        "plugin_id": plugin_id,
-                                                        "status": "error",
-                                                        "error": e.to_string(),
-                                                        "success": false
-                                                    }),
-                                                };
-                                                
-                                                let _ = ws_tx.send(PluginExecutionSession {
-                                                    id: session_id,
-                                                    plugin_id,
-                                                    status: "completed".to_string(),
-                                                    output: final_message.to_string(),
-                                                });
-                                            });
-                                        }
-                                    }
-                                    "subscribe" => {
-                                        if let Some(session_id) = data.get("session_id").and_then(|s| s.as_str()) {
-                                            let subscribe_msg = json!({
-                                                "type": "subscribed",
-                                                "session_id": session_id,
-                                                "message": "Subscribed to session updates"
-                                            });
-                                            
-                                            if let Err(_) = socket.send(Message::Text(subscribe_msg.to_string())).await {
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    _ => {
-                                        let error_msg = json!({
-                                            "type": "error",
-                                            "message": format!("Unknown command: {}", command)
-                                        });
-                                        
-                                        if let Err(_) = socket.send(Message::Text(error_msg.to_string())).await {
-                                            break;
-                                        }
-                                    }
+                        last_activity = Instant::now();
+                        if !reauthenticate(&state, &token, &mut user).await {
+                            close_socket(socket, close_code::POLICY, "Token expired or revoked").await;
+                            break;
+                        }
+                        match ClientCommand::parse(&text) {
+                            Ok(command) => {
+                                match handle_command(command, &ws_manager, &plugin_service, &job_queue, &user, &mut subscriptions, &out_tx).await {
+                                    Some(reply) => reply,
+                                    None => continue,
                                 }
                             }
+                            Err(error) => error,
                         }
                     }
+                    Some(Ok(Message::Binary(_))) => {
+                        last_activity = Instant::now();
+                        ServerEvent::error(ErrorCode::InvalidMessage, "Binary messages are not supported")
+                    }
                     Some(Ok(Message::Close(_))) => break,
                     Some(Err(_)) => break,
                     None => break,
@@ -182,24 +475,702 @@ async fn handle_plugin_execution_socket(
                 }
             }
             
-            session_result = rx.recv() => {
-                match session_result {
-                    Ok(session) => {
-                        let message = json!({
-                            "type": "update",
-                            "session_id": session.id,
-                            "plugin_id": session.plugin_id,
-                            "status": session.status,
-                            "output": session.output
-                        });
-                        
-                        if let Err(_) = socket.send(Message::Text(message.to_string())).await {
-                            break;
-                        }
+            Some(event) = out_rx.recv() => {
+                last_activity = Instant::now();
+                event
+            }
+            
+            _ = heartbeat.tick() => {
+                if let Some(reason) = heartbeat_close_reason(&config, Instant::now(), last_seen, last_activity) {
+                    println!("Closing WebSocket of {}: {}", user.username, reason);
+                    close_socket(socket, close_code::AWAY, reason).await;
+                    break;
+                }
+                if !reauthenticate(&state, &token, &mut user).await {
+                    close_socket(socket, close_code::POLICY, "Token expired or revoked").await;
+                    break;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        
+        if send_event(&mut socket, &reply).await.is_err() {
+            break;
+        }
+    }
+    
+    for (_, subscription) in subscriptions {
+        subscription.abort();
+    }
+}
+
+async fn handle_command(
+    command: ClientCommand,
+    ws_manager: &Arc<WebSocketManager>,
+    plugin_service: &Arc<PluginService>,
+    job_queue: &Arc<JobQueue>,
+    user: &UserInfo,
+    subscriptions: &mut HashMap<String, JoinHandle<()>>,
+    out_tx: &mpsc::Sender<ServerEvent>,
+) -> Option<ServerEvent> {
+    if let Err(message) = user.authorize(command.required_scope()) {
+        return Some(ServerEvent::error(ErrorCode::Forbidden, message));
+    }
+    
+    let reply = match command {
+        ClientCommand::ExecutePlugin { plugin_id, parameters, timeout } => {
+            if let Err(message) = plugin_service.check_timeout(timeout) {
+                return Some(ServerEvent::error(ErrorCode::InvalidMessage, message));
+            }
+            let plugin = match Uuid::parse_str(&plugin_id) {
+                Ok(id) => plugin_service.get_plugin_by_id(id).await,
+                Err(_) => Ok(None),
+            };
+            let plugin = match plugin {
+                Ok(Some(plugin)) => plugin,
+                Ok(None) => {
+                    return Some(ServerEvent::error(ErrorCode::PluginNotFound, format!("Plugin '{}' not found", plugin_id)));
+                }
+                Err(e) => {
+                    eprintln!("Failed to load plugin {}: {}", plugin_id, e);
+                    return Some(ServerEvent::error(ErrorCode::Internal, "Failed to load plugin"));
+                }
+            };
+            let session_id = Uuid::new_v4().to_string();
+            
+            // The plugin runs on a worker; this process relays its output and stdin.
+            let job = match job_queue
+                .enqueue(CreateJobRequest {
+                    plugin_id: plugin.id,
+                    user_id: user.user_id(),
+                    parameters,
+                    timeout_ms: timeout.map(|t| t as i64),
+                    session_id: Some(session_id.clone()),
+                })
+                .await
+            {
+                Ok(job) => job,
+                Err(e @ JobQueueError::QueueFull(_)) => {
+                    return Some(ServerEvent::error(ErrorCode::QueueFull, e.to_string()));
+                }
+                Err(JobQueueError::Database(e)) => {
+                    eprintln!("Failed to enqueue job for plugin {}: {}", plugin_id, e);
+                    return Some(ServerEvent::error(ErrorCode::Internal, "Failed to start execution"));
+                }
+            };
+            println!("User {} started plugin {} in session {}", user.username, plugin_id, session_id);
+            
+            // Subscribe before following the job so no output is missed.
+            let ws_tx = ws_manager.create_session(&session_id, &plugin_id, user, job.id);
+            if let Some(subscription) = ws_manager.subscribe(&session_id, user, None) {
+                subscriptions.insert(session_id.clone(), forward_session(session_id.clone(), subscription, out_tx.clone()));
+            }
+            
+            let reply = ServerEvent::Status {
+                session_id: session_id.clone(),
+                plugin_id: plugin_id.clone(),
+                status: SessionStatus::Starting,
+                message: "Plugin execution queued".to_string(),
+            };
+            
+            let plugin_service = plugin_service.clone();
+            let job_queue = job_queue.clone();
+            let ws_manager = ws_manager.clone();
+            tokio::spawn(async move {
+                follow_session(plugin_service, job_queue, ws_tx, job.id, plugin_id, session_id.clone()).await;
+                ws_manager.finish_session(session_id);
+            });
+            
+            reply
+        }
+        ClientCommand::Subscribe { session_id, since_seq } => {
+            match ws_manager.subscribe(&session_id, user, since_seq) {
+                Some(subscription) => {
+                    let reply = ServerEvent::Subscribed {
+                        session_id: session_id.clone(),
+                        plugin_id: subscription.plugin_id.clone(),
+                        since_seq,
+                        replayed: subscription.backlog.len(),
+                        truncated: subscription.truncated,
+                    };
+                    let forward = forward_session(session_id.clone(), subscription, out_tx.clone());
+                    if let Some(previous) = subscriptions.insert(session_id, forward) {
+                        previous.abort();
+                    }
+                    reply
+                }
+                None => ServerEvent::session_error(ErrorCode::SessionNotFound, &session_id, "Session not found"),
+            }
+        }
+        ClientCommand::Unsubscribe { session_id } => {
+            if let Some(subscription) = subscriptions.remove(&session_id) {
+                subscription.abort();
+            }
+            ServerEvent::Unsubscribed { session_id }
+        }
+        // Ownership is checked against the execution row, so sessions running in another process
+        // can be cancelled by their owner too.
+        ClientCommand::Cancel { session_id } => cancel_session(plugin_service, user, &session_id).await,
+        ClientCommand::Input { session_id, data } => {
+            if data.len() > MAX_INPUT_BYTES {
+                return Some(ServerEvent::session_error(
+                    ErrorCode::InputTooLarge,
+                    &session_id,
+                    format!("Input is limited to {} bytes per message", MAX_INPUT_BYTES),
+                ));
+            }
+            match ws_manager.session_job(&session_id, user) {
+                // Accepted input is not acknowledged; the plugin's output is the response.
+                Some(job_id) => match job_queue.write_input(job_id, Some(data.into_bytes())).await {
+                    Ok(JobInputOutcome::Accepted) => return None,
+                    Ok(JobInputOutcome::Full) => ServerEvent::session_error(
+                        ErrorCode::StdinFull,
+                        &session_id,
+                        "The plugin has not read earlier input yet; retry later",
+                    ),
+                    Ok(JobInputOutcome::Closed) => {
+                        ServerEvent::session_error(ErrorCode::StdinClosed, &session_id, "Stdin is closed")
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to queue stdin of session {}: {}", session_id, e);
+                        ServerEvent::session_error(ErrorCode::Internal, &session_id, "Failed to send input")
                     }
-                    Err(_) => break,
+                },
+                None => ServerEvent::session_error(ErrorCode::SessionNotFound, &session_id, "Session not found"),
+            }
+        }
+        ClientCommand::Eof { session_id } => match ws_manager.session_job(&session_id, user) {
+            Some(job_id) => match job_queue.write_input(job_id, None).await {
+                Ok(_) => ServerEvent::StdinClosed { session_id },
+                Err(e) => {
+                    eprintln!("Failed to close stdin of session {}: {}", session_id, e);
+                    ServerEvent::session_error(ErrorCode::Internal, &session_id, "Failed to close stdin")
+                }
+            },
+            None => ServerEvent::session_error(ErrorCode::SessionNotFound, &session_id, "Session not found"),
+        },
+    };
+    
+    Some(reply)
+}
+
+async fn cancel_session(plugin_service: &PluginService, user: &UserInfo, session_id: &str) -> ServerEvent {
+    let execution = match plugin_service.get_execution_by_session(session_id).await {
+        Ok(Some(execution)) if !user.can_access(execution.user_id) => {
+            return ServerEvent::session_error(ErrorCode::SessionNotFound, session_id, "Session not found");
+        }
+        Ok(Some(execution)) if execution.status == ExecutionStatus::Running => execution,
+        Ok(_) => {
+            return ServerEvent::session_error(ErrorCode::NotRunning, session_id, "No running execution for this session");
+        }
+        Err(e) => {
+            eprintln!("Failed to look up session {}: {}", session_id, e);
+            return ServerEvent::session_error(ErrorCode::Internal, session_id, "Failed to look up session");
+        }
+    };
+    
+    // The final "cancelled" result is broadcast by the session's executor once the guest stops.
+    match plugin_service.request_cancellation(execution.id).await {
+        Ok(()) => ServerEvent::Status {
+            session_id: session_id.to_string(),
+            plugin_id: execution.plugin_id.to_string(),
+            status: SessionStatus::Cancelling,
+            message: "Cancellation requested".to_string(),
+        },
+        Err(e) => {
+            eprintln!("Failed to cancel execution {}: {}", execution.id, e);
+            ServerEvent::session_error(ErrorCode::Internal, session_id, "Failed to cancel execution")
+        }
+    }
+}
+
+fn session_update(session_id: &str, plugin_id: &str, status: SessionStatus, stream: Option<LogStream>, output: String) -> PluginExecutionSession {
+    PluginExecutionSession {
+        id: session_id.to_string(),
+        plugin_id: plugin_id.to_string(),
+        status,
+        stream,
+        output,
+        result: None,
+    }
+}
+
+/// Relays the output stored by the worker running a session's job, then its result.
+async fn follow_session(
+    plugin_service: Arc<PluginService>,
+    job_queue: Arc<JobQueue>,
+    ws_tx: SessionSink,
+    job_id: Uuid,
+    plugin_id: String,
+    session_id: String,
+) {
+    // Subscribed before the first read, so nothing that happens in between is missed.
+    let mut finished_rx = job_queue.subscribe_finished();
+    let mut logs_rx = plugin_service.subscribe_logs();
+    let poll_interval = job_queue.config().poll_interval;
+    
+    let mut execution_id = None;
+    let mut after_seq = None;
+    let job = loop {
+        let job = match job_queue.get_job(job_id).await {
+            Ok(Some(job)) => job,
+            Ok(None) => break None,
+            Err(e) => {
+                eprintln!("Failed to load job {} of session {}: {}", job_id, session_id, e);
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            }
+        };
+        
+        // A job requeued after its worker died runs again under a new execution.
+        if job.execution_id.is_some() && job.execution_id != execution_id {
+            execution_id = job.execution_id;
+            after_seq = None;
+            ws_tx.send(session_update(&session_id, &plugin_id, SessionStatus::Running, None, "Executing plugin...".to_string()));
+        }
+        if let Some(execution_id) = execution_id {
+            after_seq = forward_logs(&plugin_service, &ws_tx, execution_id, after_seq, &plugin_id, &session_id).await;
+        }
+        // Workers store every line before they finish the job, so this last read saw all of them.
+        if job.status.is_finished() {
+            break Some(job);
+        }
+        
+        let _ = tokio::time::timeout(poll_interval, async {
+            loop {
+                tokio::select! {
+                    Ok(id) = finished_rx.recv() => if id == job_id { break },
+                    Ok(id) = logs_rx.recv() => if Some(id) == execution_id { break },
+                    else => break,
                 }
             }
+        }).await;
+    };
+    
+    let execution = match job.as_ref().and_then(|job| job.execution_id) {
+        Some(execution_id) => plugin_service.get_execution(execution_id).await.unwrap_or_else(|e| {
+            eprintln!("Failed to load execution {}: {}", execution_id, e);
+            None
+        }),
+        None => None,
+    };
+    let (status, result) = match execution {
+        Some(execution) => (execution.status.into(), ExecutionResult::from_execution(&execution)),
+        // The job ended before the plugin ran, e.g. because its execution could not be recorded.
+        None => match job {
+            Some(job) if job.status == JobStatus::Cancelled => {
+                let error = job.error.unwrap_or_else(|| "Execution was cancelled".to_string());
+                (SessionStatus::Cancelled, ExecutionResult::failed(None, error))
+            }
+            job => {
+                let error = job.and_then(|job| job.error).unwrap_or_else(|| "Execution failed".to_string());
+                (SessionStatus::Failed, ExecutionResult::failed(Some(ErrorCode::Internal), error))
+            }
+        },
+    };
+    
+    ws_tx.send(PluginExecutionSession {
+        id: session_id,
+        plugin_id,
+        status,
+        stream: None,
+        output: String::new(),
+        result: Some(result),
+    });
+}
+
+// Sends the lines stored after `after_seq` and returns the last one sent.
+async fn forward_logs(
+    plugin_service: &PluginService,
+    ws_tx: &SessionSink,
+    execution_id: Uuid,
+    mut after_seq: Option<i64>,
+    plugin_id: &str,
+    session_id: &str,
+) -> Option<i64> {
+    loop {
+        let filter = ExecutionLogFilter {
+            after_seq,
+            limit: Some(FOLLOW_PAGE),
+            ..Default::default()
+        };
+        let lines = match plugin_service.get_execution_logs(execution_id, filter).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                eprintln!("Failed to read output of execution {}: {}", execution_id, e);
+                return after_seq;
+            }
+        };
+        let complete = (lines.len() as i64) < FOLLOW_PAGE;
+        for line in lines {
+            after_seq = Some(line.seq);
+            let stream = if line.stream == LogStream::Stderr.as_str() { LogStream::Stderr } else { LogStream::Stdout };
+            ws_tx.send(session_update(session_id, plugin_id, SessionStatus::Running, Some(stream), line.line));
+        }
+        if complete {
+            return after_seq;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::database::{
+        CreateExecutionRequest, CreatePluginRequest, ExecutionHistoryFilter, ExecutionLogLine, ExecutionPage,
+        FinishExecutionRequest, NewExecutionLogLine, Plugin, PluginExecution, PluginRepository,
+        UpdatePluginRequest, UserRole,
+    };
+    use crate::plugin::CancelToken;
+    use chrono::{DateTime, Utc};
+
+    // Only the execution lookups and cancel requests the session commands make return data.
+    #[derive(Default)]
+    struct MockExecutions {
+        executions: Vec<PluginExecution>,
+        cancel_requests: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait::async_trait]
+    impl PluginRepository for MockExecutions {
+        async fn create_plugin(&self, _: CreatePluginRequest) -> Result<Plugin, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn get_plugin_by_id(&self, _: Uuid) -> Result<Option<Plugin>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn get_plugin_by_filename(&self, _: &str) -> Result<Option<Plugin>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn list_plugins(&self, _: Option<i64>, _: Option<i64>) -> Result<Vec<Plugin>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn update_plugin(&self, _: Uuid, _: UpdatePluginRequest) -> Result<Plugin, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn delete_plugin(&self, _: Uuid) -> Result<bool, sqlx::Error> {
+            Ok(false)
+        }
+
+        async fn record_execution(&self, _: CreateExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn complete_execution(&self, _: Uuid, _: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn fail_execution(
+            &self,
+            _: Uuid,
+            _: ExecutionStatus,
+            _: FinishExecutionRequest,
+        ) -> Result<PluginExecution, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn cancel_execution(&self, _: Uuid, _: FinishExecutionRequest) -> Result<PluginExecution, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn get_execution(&self, id: Uuid) -> Result<Option<PluginExecution>, sqlx::Error> {
+            Ok(self.executions.iter().find(|execution| execution.id == id).cloned())
+        }
+
+        async fn get_execution_by_session(&self, session_id: &str) -> Result<Option<PluginExecution>, sqlx::Error> {
+            Ok(self
+                .executions
+                .iter()
+                .find(|execution| execution.session_id.as_deref() == Some(session_id))
+                .cloned())
+        }
+
+        async fn request_execution_cancel(&self, id: Uuid) -> Result<(), sqlx::Error> {
+            self.cancel_requests.lock().unwrap().push(id);
+            Ok(())
+        }
+
+        async fn get_execution_history(&self, _: Uuid, _: ExecutionHistoryFilter) -> Result<ExecutionPage, sqlx::Error> {
+            Err(sqlx::Error::RowNotFound)
+        }
+
+        async fn append_execution_logs(&self, _: Vec<NewExecutionLogLine>) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn get_execution_logs(&self, _: Uuid, _: ExecutionLogFilter) -> Result<Vec<ExecutionLogLine>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn delete_execution_logs_before(&self, _: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+            Ok(0)
+        }
+    }
+
+    fn user(username: &str, role: UserRole) -> UserInfo {
+        UserInfo {
+            id: Uuid::new_v4().to_string(),
+            username: username.to_string(),
+            name: username.to_string(),
+            role,
+            is_admin: role == UserRole::Admin,
+            scopes: None,
+            api_key_id: None,
+        }
+    }
+
+    fn execution(session_id: &str, owner: &UserInfo, status: ExecutionStatus) -> PluginExecution {
+        PluginExecution {
+            id: Uuid::new_v4(),
+            plugin_id: Uuid::new_v4(),
+            user_id: owner.user_id(),
+            session_id: Some(session_id.to_string()),
+            parameters: None,
+            result: None,
+            error: None,
+            execution_time_ms: 0,
+            status,
+            started_at: Utc::now(),
+            completed_at: None,
+            peak_memory_bytes: None,
+            stderr: None,
+            exit_code: None,
+            output_truncated: false,
+        }
+    }
+
+    fn plugin_service(executions: Vec<PluginExecution>) -> (PluginService, Arc<MockExecutions>) {
+        let repo = Arc::new(MockExecutions {
+            executions,
+            ..Default::default()
+        });
+        (PluginService::new(repo.clone(), &ServerConfig::for_tests()), repo)
+    }
+
+    fn error_code(event: ServerEvent) -> ErrorCode {
+        match event {
+            ServerEvent::Error { code, .. } => code,
+            other => panic!("expected an error event, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn owner_and_admins_can_cancel_a_running_session() {
+        let alice = user("alice", UserRole::User);
+        let running = execution("s1", &alice, ExecutionStatus::Running);
+        let execution_id = running.id;
+        let (service, repo) = plugin_service(vec![running]);
+
+        let reply = cancel_session(&service, &alice, "s1").await;
+        assert!(matches!(reply, ServerEvent::Status { status: SessionStatus::Cancelling, .. }), "{:?}", reply);
+        let reply = cancel_session(&service, &user("root", UserRole::Admin), "s1").await;
+        assert!(matches!(reply, ServerEvent::Status { status: SessionStatus::Cancelling, .. }), "{:?}", reply);
+
+        // Neither run is in this process, so both requests go to the other processes.
+        assert_eq!(*repo.cancel_requests.lock().unwrap(), [execution_id, execution_id]);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_session_running_here_stops_it_without_a_notification() {
+        let alice = user("alice", UserRole::User);
+        let running = execution("s1", &alice, ExecutionStatus::Running);
+        let (service, repo) = plugin_service(vec![running.clone()]);
+        let token = service.track_execution(running.id, CancelToken::default());
+
+        cancel_session(&service, &alice, "s1").await;
+        assert!(token.is_cancelled());
+        assert!(repo.cancel_requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn other_users_cannot_cancel_or_detect_a_session() {
+        let alice = user("alice", UserRole::User);
+        let (service, repo) = plugin_service(vec![execution("s1", &alice, ExecutionStatus::Running)]);
+
+        // Someone else's session looks exactly like one that does not exist.
+        let reply = cancel_session(&service, &user("bob", UserRole::User), "s1").await;
+        assert!(matches!(error_code(reply), ErrorCode::SessionNotFound));
+        assert!(repo.cancel_requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn finished_or_unknown_sessions_are_not_running() {
+        let alice = user("alice", UserRole::User);
+        let (service, repo) = plugin_service(vec![execution("done", &alice, ExecutionStatus::Completed)]);
+
+        assert!(matches!(error_code(cancel_session(&service, &alice, "done").await), ErrorCode::NotRunning));
+        assert!(matches!(error_code(cancel_session(&service, &alice, "missing").await), ErrorCode::NotRunning));
+        assert!(repo.cancel_requests.lock().unwrap().is_empty());
+    }
+
+    fn manager() -> Arc<WebSocketManager> {
+        Arc::new(WebSocketManager::new(WebSocketConfig::default()))
+    }
+
+    fn output(sink: &SessionSink, session_id: &str, line: &str) {
+        sink.send(session_update(session_id, "p1", SessionStatus::Running, Some(LogStream::Stdout), line.to_string()));
+    }
+
+    #[test]
+    fn only_the_owner_and_admins_can_subscribe_or_reach_a_session() {
+        let manager = manager();
+        let alice = user("alice", UserRole::User);
+        let job_id = Uuid::new_v4();
+        manager.create_session("s1", "p1", &alice, job_id);
+
+        let subscription = manager.subscribe("s1", &alice, None).unwrap();
+        assert_eq!(subscription.plugin_id, "p1");
+        assert!(manager.subscribe("s1", &user("root", UserRole::Admin), None).is_some());
+        assert_eq!(manager.session_job("s1", &alice), Some(job_id));
+
+        let bob = user("bob", UserRole::User);
+        assert!(manager.subscribe("s1", &bob, None).is_none());
+        assert!(manager.session_job("s1", &bob).is_none());
+        assert!(manager.subscribe("missing", &alice, None).is_none());
+    }
+
+    #[test]
+    fn subscribers_only_receive_updates_of_their_own_session() {
+        let manager = manager();
+        let alice = user("alice", UserRole::User);
+        let first = manager.create_session("s1", "p1", &alice, Uuid::new_v4());
+        let second = manager.create_session("s2", "p1", &alice, Uuid::new_v4());
+        let mut subscription = manager.subscribe("s1", &alice, None).unwrap();
+
+        output(&second, "s2", "not for s1");
+        output(&first, "s1", "for s1");
+
+        let event = subscription.rx.try_recv().unwrap();
+        assert_eq!(event.session.id, "s1");
+        assert_eq!(event.session.output, "for s1");
+        assert!(subscription.rx.try_recv().is_err());
+    }
+
+    fn seqs(events: &[SessionEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.seq).collect()
+    }
+
+    #[test]
+    fn subscribing_with_since_seq_backfills_only_newer_events() {
+        let manager = manager();
+        let alice = user("alice", UserRole::User);
+        let sink = manager.create_session("s1", "p1", &alice, Uuid::new_v4());
+        for line in ["a", "b", "c"] {
+            output(&sink, "s1", line);
+        }
+
+        let subscription = manager.subscribe("s1", &alice, Some(0)).unwrap();
+        assert_eq!(seqs(&subscription.backlog), [1, 2]);
+        assert!(!subscription.truncated);
+
+        let subscription = manager.subscribe("s1", &alice, None).unwrap();
+        assert_eq!(seqs(&subscription.backlog), [0, 1, 2]);
+        assert!(!subscription.truncated);
+
+        // A client that has seen everything gets no backlog, only live events.
+        let mut subscription = manager.subscribe("s1", &alice, Some(2)).unwrap();
+        assert!(subscription.backlog.is_empty());
+        output(&sink, "s1", "d");
+        assert_eq!(subscription.rx.try_recv().unwrap().seq, 3);
+    }
+
+    #[test]
+    fn backfill_beyond_the_replay_buffer_is_marked_truncated() {
+        let manager = manager();
+        let alice = user("alice", UserRole::User);
+        let sink = manager.create_session("s1", "p1", &alice, Uuid::new_v4());
+        for i in 0..SESSION_REPLAY_EVENTS + 5 {
+            output(&sink, "s1", &i.to_string());
+        }
+
+        let subscription = manager.subscribe("s1", &alice, Some(2)).unwrap();
+        assert!(subscription.truncated);
+        assert_eq!(subscription.backlog.len(), SESSION_REPLAY_EVENTS);
+        assert_eq!(subscription.backlog[0].seq, 5);
+        assert!(manager.subscribe("s1", &alice, None).unwrap().truncated);
+        // Seq 4 was evicted but everything after it is still buffered.
+        assert!(!manager.subscribe("s1", &alice, Some(4)).unwrap().truncated);
+    }
+
+    #[tokio::test]
+    async fn a_slow_client_is_told_the_last_seq_it_got() {
+        let manager = manager();
+        let alice = user("alice", UserRole::User);
+        let sink = manager.create_session("s1", "p1", &alice, Uuid::new_v4());
+        let subscription = manager.subscribe("s1", &alice, None).unwrap();
+
+        // Room for a single event that nobody reads until the session has moved on.
+        let (out_tx, mut out_rx) = mpsc::channel(1);
+        for line in ["a", "b", "c"] {
+            output(&sink, "s1", line);
+        }
+        let forward = forward_session("s1".to_string(), subscription, out_tx);
+
+        assert!(matches!(out_rx.recv().await, Some(ServerEvent::Update { seq: 0, .. })));
+        match out_rx.recv().await {
+            Some(ServerEvent::Lagged { session_id, last_seq }) => {
+                assert_eq!(session_id, "s1");
+                assert_eq!(last_seq, Some(0));
+            }
+            other => panic!("expected a lagged event, got {:?}", other),
         }
+        forward.await.unwrap();
+
+        // Resubscribing from the reported seq picks up exactly what was dropped.
+        let subscription = manager.subscribe("s1", &alice, Some(0)).unwrap();
+        assert_eq!(seqs(&subscription.backlog), [1, 2]);
+    }
+
+    #[test]
+    fn connection_limits_are_per_user_and_per_server_and_freed_on_drop() {
+        let manager = Arc::new(WebSocketManager::new(WebSocketConfig {
+            max_connections: 3,
+            max_connections_per_user: 2,
+            ..Default::default()
+        }));
+        let alice = user("alice", UserRole::User);
+        let bob = user("bob", UserRole::User);
+
+        let first = manager.open_connection(&alice).unwrap();
+        let _second = manager.open_connection(&alice).unwrap();
+        assert_eq!(manager.open_connection(&alice).err(), Some("Too many connections for this user"));
+
+        let _third = manager.open_connection(&bob).unwrap();
+        assert_eq!(manager.open_connection(&bob).err(), Some("Server connection limit reached"));
+
+        drop(first);
+        assert!(manager.open_connection(&alice).is_ok());
+        assert_eq!(manager.connections.lock().unwrap().per_user.get("alice"), Some(&1));
+    }
+
+    #[test]
+    fn heartbeat_closes_sockets_that_stop_answering_or_go_idle() {
+        let config = WebSocketConfig {
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: Some(Duration::from_secs(600)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(heartbeat_close_reason(&config, at(40), start, start), None);
+        assert_eq!(heartbeat_close_reason(&config, at(41), start, start), Some("Ping timeout"));
+        // A peer answering pings but sending nothing else is closed once idle.
+        assert_eq!(heartbeat_close_reason(&config, at(600), at(590), start), None);
+        assert_eq!(heartbeat_close_reason(&config, at(601), at(590), start), Some("Idle timeout"));
+        assert_eq!(heartbeat_close_reason(&config, at(601), at(590), at(10)), None);
+
+        let never_idle = WebSocketConfig { idle_timeout: None, ..config };
+        assert_eq!(heartbeat_close_reason(&never_idle, at(10_000), at(9_990), start), None);
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,322 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::ApiScope;
+use crate::database::{ExecutionStatus, LogStream, PluginExecution};
+
+// Bumped whenever a command or event changes shape incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const COMMANDS: &[&str] = &["execute_plugin", "subscribe", "unsubscribe", "cancel", "input", "eof"];
+
+// Largest chunk of stdin accepted in a single `input` command.
+pub const MAX_INPUT_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStatus {
+    Starting,
+    Running,
+    Cancelling,
+    Completed,
+    Failed,
+    Timeout,
+    Cancelled,
+}
+
+impl From<ExecutionStatus> for SessionStatus {
+    fn from(status: ExecutionStatus) -> Self {
+        match status {
+            ExecutionStatus::Running => SessionStatus::Running,
+            ExecutionStatus::Completed => SessionStatus::Completed,
+            ExecutionStatus::Failed => SessionStatus::Failed,
+            ExecutionStatus::Timeout => SessionStatus::Timeout,
+            ExecutionStatus::Cancelled => SessionStatus::Cancelled,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ClientCommand {
+    ExecutePlugin {
+        plugin_id: String,
+        #[serde(default)]
+        parameters: Option<Value>,
+        #[serde(default)]
+        timeout: Option<u64>,
+    },
+    Subscribe {
+        session_id: String,
+        #[serde(default)]
+        since_seq: Option<u64>,
+    },
+    Unsubscribe {
+        session_id: String,
+    },
+    Cancel {
+        session_id: String,
+    },
+    Input {
+        session_id: String,
+        data: String,
+    },
+    Eof {
+        session_id: String,
+    },
+}
+
+impl ClientCommand {
+    // Scope an API key needs for this command.
+    pub fn required_scope(&self) -> ApiScope {
+        match self {
+            ClientCommand::Subscribe { .. } | ClientCommand::Unsubscribe { .. } => ApiScope::PluginsRead,
+            _ => ApiScope::PluginsExecute,
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub fn parse(text: &str) -> Result<Self, ServerEvent> {
+        let value: Value = serde_json::from_str(text)
+            .map_err(|e| ServerEvent::error(ErrorCode::InvalidMessage, format!("Malformed JSON: {}", e)))?;
+
+        let command = match value.get("command").and_then(|c| c.as_str()) {
+            Some(command) => command.to_string(),
+            None => {
+                return Err(ServerEvent::error(
+                    ErrorCode::InvalidMessage,
+                    "Message must be an object with a string \"command\" field",
+                ));
+            }
+        };
+        if !COMMANDS.contains(&command.as_str()) {
+            return Err(ServerEvent::error(
+                ErrorCode::UnknownCommand,
+                format!("Unknown command: {}", command),
+            ));
+        }
+
+        serde_json::from_value(value).map_err(|e| {
+            ServerEvent::error(ErrorCode::InvalidMessage, format!("Invalid \"{}\" command: {}", command, e))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionResult {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+    pub peak_memory_bytes: u64,
+    pub error: Option<String>,
+    // Set when the session failed in the server rather than in the plugin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+impl ExecutionResult {
+    pub fn from_execution(execution: &PluginExecution) -> Self {
+        Self {
+            success: execution.status == ExecutionStatus::Completed,
+            exit_code: execution.exit_code,
+            stdout: execution.result.clone().unwrap_or_default(),
+            stderr: execution.stderr.clone().unwrap_or_default(),
+            truncated: execution.output_truncated,
+            peak_memory_bytes: execution.peak_memory_bytes.unwrap_or(0) as u64,
+            // A plugin that ran reports its failure through its exit code and stderr.
+            error: execution.error.clone().filter(|_| execution.exit_code.is_none()),
+            code: None,
+        }
+    }
+    
+    pub fn failed(code: Option<ErrorCode>, error: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            truncated: false,
+            peak_memory_bytes: 0,
+            error: Some(error.into()),
+            code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnknownCommand,
+    SessionNotFound,
+    NotRunning,
+    StdinClosed,
+    StdinFull,
+    InputTooLarge,
+    Forbidden,
+    PluginNotFound,
+    QueueFull,
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Connected {
+        protocol_version: u32,
+        username: String,
+        message: String,
+    },
+    Status {
+        session_id: String,
+        plugin_id: String,
+        status: SessionStatus,
+        message: String,
+    },
+    Update {
+        seq: u64,
+        session_id: String,
+        plugin_id: String,
+        status: SessionStatus,
+        stream: Option<LogStream>,
+        output: String,
+    },
+    Result {
+        seq: u64,
+        session_id: String,
+        plugin_id: String,
+        status: SessionStatus,
+        #[serde(flatten)]
+        result: ExecutionResult,
+    },
+    Subscribed {
+        session_id: String,
+        plugin_id: String,
+        since_seq: Option<u64>,
+        replayed: usize,
+        truncated: bool,
+    },
+    Unsubscribed {
+        session_id: String,
+    },
+    StdinClosed {
+        session_id: String,
+    },
+    // Updates after `last_seq` were dropped because the client fell behind; resubscribe with
+    // `since_seq` set to it to catch up.
+    Lagged {
+        session_id: String,
+        last_seq: Option<u64>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+    },
+}
+
+impl ServerEvent {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerEvent::Error {
+            code,
+            message: message.into(),
+            session_id: None,
+        }
+    }
+
+    pub fn session_error(code: ErrorCode, session_id: &str, message: impl Into<String>) -> Self {
+        ServerEvent::Error {
+            code,
+            message: message.into(),
+            session_id: Some(session_id.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_code(result: Result<ClientCommand, ServerEvent>) -> ErrorCode {
+        match result {
+            Err(ServerEvent::Error { code, .. }) => code,
+            other => panic!("expected an error event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_execute_plugin_with_optional_fields() {
+        let command = ClientCommand::parse(r#"{"command": "execute_plugin", "plugin_id": "p1"}"#).unwrap();
+        assert!(matches!(
+            command,
+            ClientCommand::ExecutePlugin { ref plugin_id, parameters: None, timeout: None } if plugin_id == "p1"
+        ));
+
+        let command = ClientCommand::parse(
+            r#"{"command": "execute_plugin", "plugin_id": "p1", "parameters": {"n": 1}, "timeout": 500}"#,
+        )
+        .unwrap();
+        match command {
+            ClientCommand::ExecutePlugin { parameters, timeout, .. } => {
+                assert_eq!(parameters, Some(serde_json::json!({"n": 1})));
+                assert_eq!(timeout, Some(500));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_session_commands() {
+        let command = ClientCommand::parse(r#"{"command": "subscribe", "session_id": "s1", "since_seq": 7}"#).unwrap();
+        assert!(matches!(command, ClientCommand::Subscribe { ref session_id, since_seq: Some(7) } if session_id == "s1"));
+        assert!(matches!(command.required_scope(), ApiScope::PluginsRead));
+
+        let command = ClientCommand::parse(r#"{"command": "input", "session_id": "s1", "data": "hi\n"}"#).unwrap();
+        assert!(matches!(command, ClientCommand::Input { ref data, .. } if data == "hi\n"));
+        assert!(matches!(command.required_scope(), ApiScope::PluginsExecute));
+
+        assert!(matches!(
+            ClientCommand::parse(r#"{"command": "eof", "session_id": "s1"}"#),
+            Ok(ClientCommand::Eof { .. })
+        ));
+        assert!(matches!(
+            ClientCommand::parse(r#"{"command": "cancel", "session_id": "s1"}"#),
+            Ok(ClientCommand::Cancel { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        assert!(matches!(error_code(ClientCommand::parse("{not json")), ErrorCode::InvalidMessage));
+    }
+
+    #[test]
+    fn rejects_messages_without_a_command() {
+        assert!(matches!(error_code(ClientCommand::parse(r#"{"session_id": "s1"}"#)), ErrorCode::InvalidMessage));
+        assert!(matches!(error_code(ClientCommand::parse(r#"{"command": 42}"#)), ErrorCode::InvalidMessage));
+        assert!(matches!(error_code(ClientCommand::parse(r#"["subscribe"]"#)), ErrorCode::InvalidMessage));
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert!(matches!(
+            error_code(ClientCommand::parse(r#"{"command": "shutdown"}"#)),
+            ErrorCode::UnknownCommand
+        ));
+    }
+
+    #[test]
+    fn rejects_known_commands_with_invalid_fields() {
+        assert!(matches!(
+            error_code(ClientCommand::parse(r#"{"command": "subscribe"}"#)),
+            ErrorCode::InvalidMessage
+        ));
+        assert!(matches!(
+            error_code(ClientCommand::parse(r#"{"command": "execute_plugin", "plugin_id": "p1", "timeout": -1}"#)),
+            ErrorCode::InvalidMessage
+        ));
+    }
+}
@@ -1,3 +1,5 @@
 fn main() {
-    sandcrate_backend::run_plugin("../assets/plugins/plugin_hello.wasm").unwrap();
+    let output = sandcrate_backend::run_plugin("../assets/plugins/plugin_hello.wasm").unwrap();
+    print!("{}", output.stdout);
+    eprint!("{}", output.stderr);
 }
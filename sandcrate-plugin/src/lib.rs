@@ -1,7 +1,15 @@
 use std::ffi::CStr;
 use std::os::raw::c_char;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
-#[no_mangle]
+/// Environment variable the Sandcrate runtime uses to pass the execution
+/// request's `parameters` to the plugin, encoded as JSON. It is unset when
+/// the request carries no parameters.
+pub const PARAMETERS_ENV: &str = "SANDCRATE_PARAMETERS";
+
+// The test harness brings its own `main`.
+#[cfg_attr(not(test), no_mangle)]
 pub extern "C" fn main() {
     println!("Plugin execution started");
     if let Some(params) = get_parameters() {
@@ -22,10 +30,24 @@ pub extern "C" fn run() {
     println!("This demonstrates multiple entry points");
 }
 
-fn get_parameters() -> Option<String> {
-    None
+/// Returns the raw JSON parameters of the current execution, if any were provided.
+pub fn get_parameters() -> Option<Value> {
+    let raw = std::env::var(PARAMETERS_ENV).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+/// Decodes the parameters of the current execution into `T`.
+///
+/// Returns `Ok(None)` when no parameters were provided and an error when they
+/// do not match the expected shape.
+pub fn parameters<T: DeserializeOwned>() -> Result<Option<T>, serde_json::Error> {
+    match std::env::var(PARAMETERS_ENV) {
+        Ok(raw) => serde_json::from_str(&raw).map(Some),
+        Err(_) => Ok(None),
+    }
 }
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn process_data(input: *const c_char) -> *const c_char {
     if input.is_null() {
         return std::ptr::null();
@@ -35,6 +57,6 @@ pub extern "C" fn process_data(input: *const c_char) -> *const c_char {
         CStr::from_ptr(input).to_string_lossy().into_owned()
     };
     
-    let result = format!("Processed: {}", input_str);
-    "Data processed successfully\0".as_ptr() as *const c_char
+    let _result = format!("Processed: {}", input_str);
+    c"Data processed successfully".as_ptr()
 }
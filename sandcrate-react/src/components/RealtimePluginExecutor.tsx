@@ -22,16 +22,59 @@ interface RealtimeExecutionProps {
   onClose: () => void;
 }
 
-interface WebSocketMessage {
-  type: 'connected' | 'status' | 'update' | 'result' | 'error' | 'subscribed';
-  session_id?: string;
-  plugin_id?: string;
-  status?: string;
-  message?: string;
-  output?: string;
-  error?: string;
-  success?: boolean;
-}
+// Must match PROTOCOL_VERSION in the backend's ws_protocol module.
+const PROTOCOL_VERSION = 1;
+
+type SessionStatus = 'starting' | 'running' | 'cancelling' | 'completed' | 'failed' | 'timeout' | 'cancelled';
+
+type ErrorCode =
+  | 'invalid_message'
+  | 'unknown_command'
+  | 'session_not_found'
+  | 'not_running'
+  | 'stdin_closed'
+  | 'stdin_full'
+  | 'input_too_large'
+  | 'internal';
+
+type ServerEvent =
+  | { type: 'connected'; protocol_version: number; username: string; message: string }
+  | { type: 'status'; session_id: string; plugin_id: string; status: SessionStatus; message: string }
+  | {
+      type: 'update';
+      seq: number;
+      session_id: string;
+      plugin_id: string;
+      status: SessionStatus;
+      stream: 'stdout' | 'stderr' | null;
+      output: string;
+    }
+  | {
+      type: 'result';
+      seq: number;
+      session_id: string;
+      plugin_id: string;
+      status: SessionStatus;
+      success: boolean;
+      exit_code: number | null;
+      stdout: string;
+      stderr: string;
+      truncated: boolean;
+      peak_memory_bytes: number;
+      error: string | null;
+    }
+  | {
+      type: 'subscribed';
+      session_id: string;
+      plugin_id: string;
+      since_seq: number | null;
+      replayed: number;
+      truncated: boolean;
+    }
+  | { type: 'unsubscribed'; session_id: string }
+  | { type: 'stdin_closed'; session_id: string }
+  | { type: 'lagged'; session_id: string; last_seq: number | null }
+  | { type: 'error'; code: ErrorCode; message: string; session_id?: string };
 
 export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
   plugin,
@@ -45,8 +88,11 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
   const [executionStatus, setExecutionStatus] = useState<string>('idle');
   const [parameters, setParameters] = useState('');
   const [error, setError] = useState<string | null>(null);
+  const [stdinInput, setStdinInput] = useState('');
+  const [stdinClosed, setStdinClosed] = useState(false);
   
   const wsRef = useRef<WebSocket | null>(null);
+  const lastSeqRef = useRef<number | null>(null);
   const outputEndRef = useRef<HTMLDivElement>(null);
 
   useEffect(() => {
@@ -64,9 +110,11 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
   const connectWebSocket = () => {
     const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
     const token = localStorage.getItem('authToken');
-    const wsUrl = token 
-      ? `ws://127.0.0.1:3000/ws/plugins?token=${encodeURIComponent(token)}`
-      : `ws://127.0.0.1:3000/ws/plugins`;
+    if (!token) {
+      setError('You must be logged in to execute plugins');
+      return;
+    }
+    const wsUrl = `ws://127.0.0.1:3000/ws/plugins?protocol=${PROTOCOL_VERSION}&token=${encodeURIComponent(token)}`;
     
     console.log('Attempting to connect to WebSocket:', wsUrl);
     
@@ -83,7 +131,7 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
     ws.onmessage = (event) => {
       console.log('📥 WebSocket message received:', event.data);
       try {
-        const message: WebSocketMessage = JSON.parse(event.data);
+        const message: ServerEvent = JSON.parse(event.data);
         handleWebSocketMessage(message);
       } catch (err) {
         console.error('❌ Failed to parse WebSocket message:', err);
@@ -94,7 +142,13 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
     ws.onclose = (event) => {
       setIsConnected(false);
       console.log('🔌 WebSocket disconnected:', event.code, event.reason);
-      setOutput(prev => [...prev, `🔌 WebSocket disconnected (${event.code})`]);
+      setOutput(prev => [...prev, `🔌 WebSocket disconnected (${event.code}${event.reason ? `: ${event.reason}` : ''})`]);
+      if (event.code === 1013) {
+        setError(event.reason || 'Too many connections, try again later');
+      }
+      if (event.code === 1008) {
+        setError(event.reason || 'Your session has ended, reconnect to continue');
+      }
     };
 
     ws.onerror = (error) => {
@@ -104,51 +158,97 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
     };
   };
 
-  const handleWebSocketMessage = (message: WebSocketMessage) => {
+  const handleWebSocketMessage = (message: ServerEvent) => {
     switch (message.type) {
-      case 'connected':
-        setOutput(prev => [...prev, `🔗 ${message.message || 'WebSocket connected'}`]);
+      case 'connected': {
+        setOutput(prev => [...prev, `🔗 ${message.message}`]);
+        // Resume the session that was running before a reload or reconnect.
+        const previousSession = plugin ? sessionStorage.getItem(`realtimeSession:${plugin.id}`) : null;
+        if (previousSession && wsRef.current) {
+          setSessionId(previousSession);
+          const command: Record<string, unknown> = { command: 'subscribe', session_id: previousSession };
+          if (lastSeqRef.current !== null) {
+            command.since_seq = lastSeqRef.current;
+          }
+          wsRef.current.send(JSON.stringify(command));
+        }
         break;
+      }
         
       case 'status':
+        setExecutionStatus(message.status);
         if (message.status === 'starting') {
-          setExecutionStatus('starting');
-          setOutput(prev => [...prev, `🚀 ${message.message || 'Plugin execution started'}`]);
+          setSessionId(message.session_id);
+          setStdinClosed(false);
+          lastSeqRef.current = null;
+          if (plugin) {
+            sessionStorage.setItem(`realtimeSession:${plugin.id}`, message.session_id);
+          }
+          setOutput(prev => [...prev, `🚀 ${message.message}`]);
+        } else if (message.status === 'cancelling') {
+          setOutput(prev => [...prev, `🛑 ${message.message}`]);
         }
         break;
       
-      case 'update':
-        if (message.output) {
-          setOutput(prev => [...prev, message.output]);
-        }
-        if (message.status) {
-          setExecutionStatus(message.status);
-        }
+      case 'update': {
+        lastSeqRef.current = message.seq;
+        const line = message.stream === 'stderr' ? `ERROR: ${message.output}` : message.output;
+        setOutput(prev => [...prev, line]);
+        setExecutionStatus(message.status);
         break;
+      }
       
       case 'result':
+        lastSeqRef.current = message.seq;
+        setExecutionStatus(message.status);
         if (message.success) {
-          setExecutionStatus('completed');
           setOutput(prev => [...prev, `✅ Plugin execution completed successfully`]);
-          if (message.output) {
-            setOutput(prev => [...prev, `📄 Final output: ${message.output}`]);
-          }
+        } else if (message.status === 'cancelled') {
+          setOutput(prev => [...prev, `🛑 Plugin execution cancelled`]);
         } else {
-          setExecutionStatus('error');
-          setError(message.error || 'Plugin execution failed');
-          setOutput(prev => [...prev, `❌ Plugin execution failed: ${message.error}`]);
+          const reason = message.error || `exit code ${message.exit_code}`;
+          setError(reason);
+          setOutput(prev => [...prev, `❌ Plugin execution ${message.status}: ${reason}`]);
+        }
+        if (message.truncated) {
+          setOutput(prev => [...prev, '⚠️ Output was truncated']);
         }
         setIsExecuting(false);
         break;
       
       case 'error':
-        setError(message.message || 'An error occurred');
-        setOutput(prev => [...prev, `❌ Error: ${message.message}`]);
+        setError(message.message);
+        setOutput(prev => [...prev, `❌ Error (${message.code}): ${message.message}`]);
+        if (message.code === 'session_not_found' && plugin && message.session_id) {
+          sessionStorage.removeItem(`realtimeSession:${plugin.id}`);
+        }
         break;
       
       case 'subscribed':
-        setOutput(prev => [...prev, `📡 ${message.message || 'Subscribed to session updates'}`]);
+        setOutput(prev => [...prev, `📡 Subscribed to session updates (${message.replayed} replayed)`]);
+        if (message.truncated) {
+          setOutput(prev => [...prev, '⚠️ Some earlier output is no longer available']);
+        }
+        break;
+      
+      case 'unsubscribed':
+        setOutput(prev => [...prev, '📴 Unsubscribed from session updates']);
+        break;
+      
+      case 'stdin_closed':
+        setStdinClosed(true);
+        setOutput(prev => [...prev, '⏏️ Input closed']);
+        break;
+
+      case 'lagged': {
+        // The server dropped updates we could not keep up with; catch up from its replay buffer.
+        const command: Record<string, unknown> = { command: 'subscribe', session_id: message.session_id };
+        if (message.last_seq !== null) {
+          command.since_seq = message.last_seq;
+        }
+        wsRef.current?.send(JSON.stringify(command));
         break;
+      }
     }
   };
 
@@ -181,7 +281,25 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
     wsRef.current.send(JSON.stringify(command));
   };
 
+  const sendInput = () => {
+    if (!wsRef.current || !sessionId || !isConnected) return;
+
+    const data = `${stdinInput}\n`;
+    wsRef.current.send(JSON.stringify({ command: 'input', session_id: sessionId, data }));
+    setOutput(prev => [...prev, `> ${stdinInput}`]);
+    setStdinInput('');
+  };
+
+  const closeInput = () => {
+    if (!wsRef.current || !sessionId || !isConnected) return;
+    wsRef.current.send(JSON.stringify({ command: 'eof', session_id: sessionId }));
+  };
+
   const stopExecution = () => {
+    if (wsRef.current && sessionId && isConnected) {
+      wsRef.current.send(JSON.stringify({ command: 'cancel', session_id: sessionId }));
+      return;
+    }
     if (wsRef.current) {
       wsRef.current.close();
       setIsExecuting(false);
@@ -309,7 +427,7 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
                   executionStatus === 'starting' ? 'bg-yellow-100 text-yellow-800' :
                   executionStatus === 'running' ? 'bg-blue-100 text-blue-800' :
                   executionStatus === 'completed' ? 'bg-green-100 text-green-800' :
-                  executionStatus === 'error' ? 'bg-red-100 text-red-800' :
+                  executionStatus === 'failed' || executionStatus === 'timeout' ? 'bg-red-100 text-red-800' :
                   'bg-gray-100 text-gray-800'
                 }`}>
                   {executionStatus}
@@ -340,6 +458,36 @@ export const RealtimePluginExecutor: React.FC<RealtimeExecutionProps> = ({
             </div>
           </div>
 
+          <div className="px-6 py-3 border-t flex items-center space-x-2">
+            <input
+              type="text"
+              value={stdinInput}
+              onChange={(e) => setStdinInput(e.target.value)}
+              onKeyDown={(e) => {
+                if (e.key === 'Enter') {
+                  sendInput();
+                }
+              }}
+              placeholder={stdinClosed ? 'Input closed' : 'Send a line to the plugin\'s stdin...'}
+              className="flex-1 p-2 border border-gray-300 rounded-md focus:outline-none focus:ring-2 focus:ring-green-500 focus:border-green-500 font-mono text-sm"
+              disabled={!isExecuting || stdinClosed}
+            />
+            <button
+              onClick={sendInput}
+              disabled={!isExecuting || stdinClosed}
+              className="px-4 py-2 bg-green-600 text-white rounded-md hover:bg-green-700 disabled:opacity-50 disabled:cursor-not-allowed"
+            >
+              Send
+            </button>
+            <button
+              onClick={closeInput}
+              disabled={!isExecuting || stdinClosed}
+              className="px-4 py-2 bg-gray-600 text-white rounded-md hover:bg-gray-700 disabled:opacity-50 disabled:cursor-not-allowed"
+            >
+              EOF
+            </button>
+          </div>
+
           {error && (
             <div className="p-6 border-t bg-red-50">
               <div className="flex items-center">
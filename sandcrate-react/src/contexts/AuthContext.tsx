@@ -1,11 +1,11 @@
-import React, { createContext, useContext, useState, useEffect } from 'react';
+import React, { createContext, useContext, useState, useEffect, useRef } from 'react';
 import type { ReactNode } from 'react';
 
 interface User {
   id: string;
   username: string;
   name: string;
-  role: string;
+  role: 'admin' | 'user' | 'guest';
   is_admin: boolean;
 }
 
@@ -16,6 +16,24 @@ interface AuthContextType {
   logout: () => void;
 }
 
+interface TokenResponse {
+  token: string;
+  refresh_token: string;
+  user: User;
+  expires_at: string;
+  refresh_expires_at: string;
+}
+
+// Refresh the access token this long before it expires.
+const REFRESH_MARGIN_MS = 60 * 1000;
+
+const clearStoredSession = () => {
+  localStorage.removeItem('authToken');
+  localStorage.removeItem('refreshToken');
+  localStorage.removeItem('tokenExpiresAt');
+  localStorage.removeItem('user');
+};
+
 const AuthContext = createContext<AuthContextType | undefined>(undefined);
 
 export const useAuth = () => {
@@ -33,6 +51,56 @@ interface AuthProviderProps {
 export const AuthProvider: React.FC<AuthProviderProps> = ({ children }) => {
   const [user, setUser] = useState<User | null>(null);
   const [loading, setLoading] = useState(true);
+  const refreshTimerRef = useRef<ReturnType<typeof setTimeout> | null>(null);
+
+  const storeSession = (data: TokenResponse) => {
+    localStorage.setItem('authToken', data.token);
+    localStorage.setItem('refreshToken', data.refresh_token);
+    localStorage.setItem('tokenExpiresAt', data.expires_at);
+    localStorage.setItem('user', JSON.stringify(data.user));
+    setUser(data.user);
+    scheduleRefresh(data.expires_at);
+  };
+
+  const refreshSession = async (): Promise<boolean> => {
+    const refreshToken = localStorage.getItem('refreshToken');
+    if (!refreshToken) return false;
+
+    const response = await fetch('/auth/refresh', {
+      method: 'POST',
+      headers: {
+        'Content-Type': 'application/json',
+      },
+      body: JSON.stringify({ refresh_token: refreshToken }),
+    });
+
+    if (!response.ok) {
+      clearStoredSession();
+      setUser(null);
+      return false;
+    }
+
+    storeSession(await response.json());
+    return true;
+  };
+
+  const scheduleRefresh = (expiresAt: string) => {
+    if (refreshTimerRef.current) {
+      clearTimeout(refreshTimerRef.current);
+    }
+    const delay = Math.max(new Date(expiresAt).getTime() - Date.now() - REFRESH_MARGIN_MS, 0);
+    refreshTimerRef.current = setTimeout(() => {
+      refreshSession().catch(error => console.error('Token refresh failed:', error));
+    }, delay);
+  };
+
+  useEffect(() => {
+    return () => {
+      if (refreshTimerRef.current) {
+        clearTimeout(refreshTimerRef.current);
+      }
+    };
+  }, []);
 
   useEffect(() => {
     const checkAuth = async () => {
@@ -53,16 +121,17 @@ export const AuthProvider: React.FC<AuthProviderProps> = ({ children }) => {
             const data = await response.json();
             console.log('User data received:', data);
             setUser(data);
-          } else {
+            const expiresAt = localStorage.getItem('tokenExpiresAt');
+            if (expiresAt) {
+              scheduleRefresh(expiresAt);
+            }
+          } else if (!(await refreshSession())) {
             console.log('Token validation failed, clearing storage');
-            localStorage.removeItem('authToken');
-            localStorage.removeItem('user');
           }
         }
       } catch (error) {
         console.error('Auth check failed:', error);
-        localStorage.removeItem('authToken');
-        localStorage.removeItem('user');
+        clearStoredSession();
       } finally {
         setLoading(false);
       }
@@ -90,11 +159,7 @@ export const AuthProvider: React.FC<AuthProviderProps> = ({ children }) => {
         throw new Error(errorData.message || 'Authentication failed');
       }
 
-      const data = await response.json();
-      
-      localStorage.setItem('authToken', data.token);
-      localStorage.setItem('user', JSON.stringify(data.user));
-      setUser(data.user);
+      storeSession(await response.json());
     } catch (error) {
       console.error('Login failed:', error);
       throw new Error(error instanceof Error ? error.message : 'Login failed. Please check your credentials.');
@@ -104,8 +169,23 @@ export const AuthProvider: React.FC<AuthProviderProps> = ({ children }) => {
   };
 
   const logout = () => {
-    localStorage.removeItem('authToken');
-    localStorage.removeItem('user');
+    const token = localStorage.getItem('authToken');
+    const refreshToken = localStorage.getItem('refreshToken');
+    if (token) {
+      // Revoke server-side too; the local session is cleared regardless of the outcome.
+      fetch('/auth/logout', {
+        method: 'POST',
+        headers: {
+          'Authorization': `Bearer ${token}`,
+          'Content-Type': 'application/json',
+        },
+        body: JSON.stringify({ refresh_token: refreshToken }),
+      }).catch(error => console.error('Logout request failed:', error));
+    }
+    if (refreshTimerRef.current) {
+      clearTimeout(refreshTimerRef.current);
+    }
+    clearStoredSession();
     setUser(null);
   };
 
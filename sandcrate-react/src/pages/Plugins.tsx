@@ -24,7 +24,11 @@ interface Plugin {
 
 interface ExecutionResult {
   success: boolean;
+  status?: string;
   result: string;
+  stderr?: string;
+  exit_code?: number | null;
+  truncated?: boolean;
   execution_time_ms: number;
   error?: string;
 }
@@ -162,6 +166,17 @@ const ExecutionModal: React.FC<ExecutionModalProps> = ({ plugin, isOpen, onClose
                 </div>
               )}
               
+              {result.stderr && (
+                <div>
+                  <label className="block text-sm font-medium text-gray-700 mb-1">
+                    Stderr:
+                  </label>
+                  <pre className="bg-gray-50 p-3 rounded-md text-sm font-mono overflow-x-auto">
+                    {result.stderr}
+                  </pre>
+                </div>
+              )}
+              
               {result.error && (
                 <div>
                   <label className="block text-sm font-medium text-gray-700 mb-1">
@@ -182,6 +197,9 @@ const ExecutionModal: React.FC<ExecutionModalProps> = ({ plugin, isOpen, onClose
 
 export const Plugins: React.FC = () => {
   const { user } = useAuth();
+  // Mirrors the server's role checks; the API rejects these actions either way.
+  const canExecute = user?.role === 'admin' || user?.role === 'user';
+  const canManage = user?.role === 'admin';
   const [plugins, setPlugins] = useState<Plugin[]>([]);
   const [filteredPlugins, setFilteredPlugins] = useState<Plugin[]>([]);
   const [loading, setLoading] = useState(true);
@@ -392,13 +410,15 @@ export const Plugins: React.FC = () => {
           <h1 className="text-2xl font-bold text-gray-900">Plugins</h1>
           <p className="text-gray-600">WASM plugins from assets/plugins directory</p>
         </div>
-        <button
-          onClick={() => setIsUploadModalOpen(true)}
-          className="flex items-center px-4 py-2 bg-green-600 text-white rounded-md hover:bg-green-700 transition-colors"
-        >
-          <ArrowUpTrayIcon className="w-4 h-4 mr-2" />
-          Upload Plugin
-        </button>
+        {canManage && (
+          <button
+            onClick={() => setIsUploadModalOpen(true)}
+            className="flex items-center px-4 py-2 bg-green-600 text-white rounded-md hover:bg-green-700 transition-colors"
+          >
+            <ArrowUpTrayIcon className="w-4 h-4 mr-2" />
+            Upload Plugin
+          </button>
+        )}
       </div>
 
       {error && (
@@ -474,22 +494,26 @@ export const Plugins: React.FC = () => {
 
               <div className="flex items-center justify-between pt-4 border-t border-gray-200">
                 <div className="flex space-x-2">
-                  <button
-                    onClick={() => handleExecutePlugin(plugin)}
-                    className="flex items-center px-3 py-1 bg-green-600 text-white text-sm rounded-md hover:bg-green-700 transition-colors"
-                    title="Execute Plugin"
-                  >
-                    <PlayIcon className="w-4 h-4 mr-1" />
-                    Execute
-                  </button>
-                  <button
-                    onClick={() => handleRealtimeExecutePlugin(plugin)}
-                    className="flex items-center px-3 py-1 bg-blue-600 text-white text-sm rounded-md hover:bg-blue-700 transition-colors"
-                    title="Real-time Execution"
-                  >
-                    <BoltIcon className="w-4 h-4 mr-1" />
-                    Real-time
-                  </button>
+                  {canExecute && (
+                    <>
+                      <button
+                        onClick={() => handleExecutePlugin(plugin)}
+                        className="flex items-center px-3 py-1 bg-green-600 text-white text-sm rounded-md hover:bg-green-700 transition-colors"
+                        title="Execute Plugin"
+                      >
+                        <PlayIcon className="w-4 h-4 mr-1" />
+                        Execute
+                      </button>
+                      <button
+                        onClick={() => handleRealtimeExecutePlugin(plugin)}
+                        className="flex items-center px-3 py-1 bg-blue-600 text-white text-sm rounded-md hover:bg-blue-700 transition-colors"
+                        title="Real-time Execution"
+                      >
+                        <BoltIcon className="w-4 h-4 mr-1" />
+                        Real-time
+                      </button>
+                    </>
+                  )}
                   <button
                     onClick={() => {}}
                     className="p-2 text-blue-600 hover:bg-blue-50 rounded-md transition-colors"
@@ -497,13 +521,15 @@ export const Plugins: React.FC = () => {
                   >
                     <PuzzlePieceIcon className="w-4 h-4" />
                   </button>
-                  <button
-                    onClick={() => handleDeletePlugin(plugin.id)}
-                    className="p-2 text-red-600 hover:bg-red-50 rounded-md transition-colors"
-                    title="Delete Plugin"
-                  >
-                    <TrashIcon className="w-4 h-4" />
-                  </button>
+                  {canManage && (
+                    <button
+                      onClick={() => handleDeletePlugin(plugin.id)}
+                      className="p-2 text-red-600 hover:bg-red-50 rounded-md transition-colors"
+                      title="Delete Plugin"
+                    >
+                      <TrashIcon className="w-4 h-4" />
+                    </button>
+                  )}
                 </div>
               </div>
             </div>